@@ -1,6 +1,9 @@
 use clap::{Parser, Subcommand};
 use clap_complete::Shell;
 
+use crate::config::HttpMode;
+use crate::stream::StreamMode;
+
 /// Manage configuration of reverse proxies for local development domain using Nginx.
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -17,6 +20,7 @@ pub struct Args {
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum Commands {
     /// List all the servers and their proxies
     List {},
@@ -37,27 +41,204 @@ pub enum Commands {
     Add {
         /// Name of the server to configure, if found will update the config else will create a new config. Auto adds a .localdev domain.
         server_name: String,
-        /// The default (/) proxy target, eg: http://localhost:3000
-        #[clap(default_value = "http://localhost:3000")]
-        default_target: String,
-        /// The websocket proxy, eg: --ws ws:localhost:3000, added by default
-        #[clap(short, long, default_value = "/ws:localhost:3000")]
-        ws: String,
-        /// Other proxies, for example for a backend: api=http://localhost:8080 or api:8080
+        /// The default (/) proxy target, eg: http://localhost:3000 (the default), fcgi://127.0.0.1:9000, uwsgi+unix:/run/uwsgi.sock
+        /// or grpc://localhost:50051
+        default_target: Option<String>,
+        /// Websocket proxies through an upstream keeping each client on the same server, eg: --ws ws:localhost:3000,
+        /// added by default as /ws:localhost:3000
+        #[clap(short, long)]
+        ws: Vec<String>,
+        /// Websocket proxies passed directly to their target, eg: --ws-plain live=:4000
+        #[clap(long)]
+        ws_plain: Vec<String>,
+        /// Remove the websocket proxies, or do not add the default one
+        #[clap(long)]
+        no_ws: bool,
+        /// Other proxies, for example for a backend: api=http://localhost:8080 or api:8080, several servers separated
+        /// by commas are balanced with their weight and backups like api=:8080*3,:8081,:8082+backup
         #[clap(short, long)]
         proxy: Vec<String>,
+        /// How the locations with several servers balance them: round-robin (the default), ip-hash or least-conn,
+        /// eg: --balance api=least-conn or --balance ip-hash for /
+        #[clap(long)]
+        balance: Vec<String>,
+        /// Streaming proxies for Server-Sent Events, without buffering and with a long read timeout: /events=:8080,
+        /// or the name of a current location like events
+        #[clap(long)]
+        sse: Vec<String>,
+        /// Proxies for the paths matching a regex, they get the full path: '^/api/v[0-9]+/=:8080'
+        #[clap(long)]
+        regex: Vec<String>,
+        /// Locations giving their full path to the target instead of removing their prefix, eg: --preserve-prefix api
+        #[clap(long)]
+        preserve_prefix: Vec<String>,
+        /// Locations matching only their exact path, eg: --exact /health
+        #[clap(long)]
+        exact: Vec<String>,
+        /// Locations also serving their path without the trailing slash instead of redirecting it, eg: --no-redirect api
+        #[clap(long)]
+        no_redirect: Vec<String>,
+        /// gRPC locations for browsers using gRPC-Web, proxied over HTTP to a server handling it with CORS, eg: --grpc-web api
+        #[clap(long)]
+        grpc_web: Vec<String>,
+        /// Static files locations, for example for a built app: /=./dist or uploads=./uploads
+        #[clap(short = 's', long = "static")]
+        static_dir: Vec<String>,
+        /// Locations returning a canned response matching their exact path, eg: '/api/flags=200:./flags.json',
+        /// 'health=200:ok', 'api/user=200:application/json:{"id": 1}' with a content type or 'old=301:/new'
+        #[clap(long)]
+        stub: Vec<String>,
+        /// Static locations are for a SPA and fall back to their index.html
+        #[clap(long)]
+        spa: bool,
+        /// Document root of the FastCGI targets, like the public directory of a PHP app
+        #[clap(long, default_value = ".")]
+        root: String,
+        /// Headers added to the requests given to the proxies, eg: 'X-Forwarded-Proto: https' or for a location 'api=X-User: dev'
+        #[clap(short = 'H', long)]
+        header: Vec<String>,
+        /// Headers added to the responses, eg: 'Access-Control-Allow-Origin: *' or for a location 'api=X-Frame-Options: DENY'
+        #[clap(long)]
+        response_header: Vec<String>,
+        /// Origins allowed to call the locations from another server, eg: app, '*.localdev' for all the local servers,
+        /// '*' for any origin or for a location 'api=app,http://localhost:5173'
+        #[clap(long)]
+        cors: Vec<String>,
+        /// Bandwidth of the responses to reproduce a slow network, eg: 50k bytes per second or for a location api=1m,
+        /// off to remove it
+        #[clap(long)]
+        limit_rate: Vec<String>,
+        /// Requests allowed per client, the burst requests are delayed to that rate and the others get a 429,
+        /// eg: 2r/s or for a location 'api=30r/m,burst=10', off to remove it
+        #[clap(long)]
+        limit_req: Vec<String>,
+        /// Connections allowed at the same time per client, eg: 2 or for a location api=1, off to remove it
+        #[clap(long)]
+        limit_conn: Vec<String>,
+        /// How the server answers on HTTP: both to serve it like HTTPS (the default), redirect to HTTPS or https-only
+        #[clap(long, arg_enum)]
+        http: Option<HttpMode>,
+        /// Also listen with HTTP/3, needs nginx built with the http_v3_module
+        #[clap(long)]
+        http3: bool,
+        /// Largest request body allowed, like for file uploads, eg: 100m, 0 for no limit or for a location api=1g
+        #[clap(long)]
+        max_body_size: Vec<String>,
+        /// Timeout between two reads of the response of the targets, eg: 1h for long polling or for a location api=5m
+        #[clap(long)]
+        read_timeout: Vec<String>,
+        /// Timeout between two writes of the request to the targets, eg: 5m or for a location api=5m
+        #[clap(long)]
+        send_timeout: Vec<String>,
+        /// Buffering of the responses of the targets, off for streaming like SSE, eg: off or for a location events=off
+        #[clap(long)]
+        buffering: Vec<String>,
+        /// Users allowed with basic auth as user:password, kept hashed in a htpasswd file managed by localdev,
+        /// off to remove them
+        #[clap(long)]
+        basic_auth: Vec<String>,
+        /// CA file the client certificates must be signed by (mTLS): mkcert to use the mkcert CA or the path
+        /// of a CA file, off to remove it
+        #[clap(long)]
+        client_ca: Option<String>,
+        /// How the certificate of https and grpcs targets is verified: off (the default), mkcert to use the mkcert CA or the path of a CA file
+        #[clap(long)]
+        upstream_verify: Option<String>,
+        /// Client certificate file for https and grpcs targets requiring one
+        #[clap(long, requires = "upstream-key")]
+        upstream_cert: Option<String>,
+        /// Client certificate key file for https and grpcs targets requiring one
+        #[clap(long, requires = "upstream-cert")]
+        upstream_key: Option<String>,
         /// Force the reconfiguration even if the server is already configured
         #[clap(long)]
         force: bool,
+        /// Update the server if it is already configured, only changing the given locations and options
+        #[clap(short, long, conflicts_with = "force")]
+        update: bool,
         /// If we should open it in the browser right after adding it
         #[clap(short, long)]
         open: bool,
     },
+    /// Serve a directory under HTTPS on a server named after it and open it in the browser
+    ServeDir {
+        /// The directory to serve, eg: ./dist
+        path: String,
+        /// Name of the server instead of the directory name. Auto adds a .localdev domain.
+        #[clap(long)]
+        name: Option<String>,
+        /// Fall back to the index.html for a SPA
+        #[clap(long)]
+        spa: bool,
+        /// List the directory contents
+        #[clap(short, long)]
+        autoindex: bool,
+        /// Cache headers as a nginx expires value, eg: 1h, max, or epoch to disable caching
+        #[clap(short, long)]
+        cache: Option<String>,
+        /// Force the reconfiguration even if the server is already configured
+        #[clap(long)]
+        force: bool,
+        /// Do not open it in the browser
+        #[clap(long)]
+        no_open: bool,
+    },
     /// Remove a server or proxy
     Remove {
         /// Name of the server to remove.
         server_name: String,
     },
+    /// Import servers from another configuration
+    Import {
+        #[clap(subcommand)]
+        source: ImportSource,
+    },
+    /// Check the nginx version and the modules needed by the features and servers
+    Doctor {},
+    /// Manage the TCP and UDP proxies, like for databases
+    Stream {
+        #[clap(subcommand)]
+        command: StreamCommand,
+    },
+    /// Show the requests of a server from its access log, or its error log
+    Logs {
+        /// Name of the server, will also try with adding a .localdev domain.
+        server_name: String,
+        /// Keep showing the new requests
+        #[clap(short, long)]
+        follow: bool,
+        /// Only the requests with this status, eg: 404 or 5xx
+        #[clap(long)]
+        status: Option<String>,
+        /// Only the requests under this path, eg: /api
+        #[clap(long)]
+        path: Option<String>,
+        /// Number of last requests shown
+        #[clap(short = 'n', long, default_value = "20")]
+        lines: usize,
+        /// Show the error log instead
+        #[clap(short, long, conflicts_with_all = &["status", "path"])]
+        errors: bool,
+    },
+    /// Sum up the traffic of a server from its access log: the status classes, slowest paths and errors by location
+    Stats {
+        /// Name of the server, will also try with adding a .localdev domain.
+        server_name: String,
+        /// Number of slowest paths shown
+        #[clap(short, long, default_value = "5")]
+        slowest: usize,
+        /// Print it as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Mint a client certificate signed by the mkcert CA, for the servers using --client-ca mkcert
+    ClientCert {
+        /// Name of the client, the files are written in the current directory
+        name: String,
+        /// Also write a PKCS#12 file to import in a browser
+        #[clap(long)]
+        pkcs12: bool,
+    },
     /// Reload nginx config
     Reload {},
     /// Generate completion script
@@ -66,3 +247,47 @@ pub enum Commands {
         shell: Shell,
     },
 }
+
+#[derive(Subcommand, Debug)]
+pub enum ImportSource {
+    /// Add the servers for the published ports of docker compose services, running it again updates them
+    Compose {
+        /// Path of the compose file, defaults to compose.yaml or docker-compose.yml in the current directory
+        file: Option<String>,
+        /// Route all the services by path under this single server instead of one server per service
+        #[clap(short, long)]
+        server: Option<String>,
+        /// Force the reconfiguration of servers that were not imported from this file
+        #[clap(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StreamCommand {
+    /// List the TCP and UDP proxies
+    List {},
+    /// Add a TCP or UDP proxy to a server name on a port, eg: postgres 5432 :15432
+    Add {
+        /// Name of the server. Auto adds a .localdev domain.
+        server_name: String,
+        /// The port to listen on
+        port: u16,
+        /// The address to proxy to, eg: :15432, db.internal:5432 or unix:/run/redis.sock
+        target: String,
+        /// tcp or udp alone on the port, tls to terminate TLS with the mkcert certificate or sni to pass TLS through,
+        /// both routed by server name so several servers can share the port
+        #[clap(short, long, arg_enum, default_value = "tcp")]
+        mode: StreamMode,
+        /// Force the reconfiguration even if the stream is already configured
+        #[clap(long)]
+        force: bool,
+    },
+    /// Remove the proxies of a server name, or only the one on a port
+    Remove {
+        /// Name of the server to remove.
+        server_name: String,
+        /// Only remove the proxy on this port
+        port: Option<u16>,
+    },
+}
@@ -0,0 +1,165 @@
+use crate::config::{Location, Target, WebSocket};
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+/// The compose file names looked up in the current directory, in the docker compose order
+pub const DEFAULT_FILES: [&str; 4] = [
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yml",
+    "docker-compose.yaml",
+];
+
+#[derive(Deserialize, Debug)]
+struct ComposeFile {
+    #[serde(default)]
+    services: BTreeMap<String, Service>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Service {
+    #[serde(default)]
+    ports: Vec<Value>,
+    #[serde(rename = "x-localdev")]
+    localdev: Option<Extension>,
+}
+
+/// The x-localdev extension keys of a service
+#[derive(Deserialize, Debug, Default)]
+struct Extension {
+    /// Name of the server, the .localdev domain is added if missing
+    hostname: Option<String>,
+    /// Location of the proxy in the server
+    path: Option<String>,
+    /// Websocket location, eg: /ws to use the service port or /ws:3001
+    websocket: Option<String>,
+    /// Published port to use when the service has more than one
+    port: Option<u16>,
+}
+
+/// A server to configure from the compose services
+#[derive(Debug)]
+pub struct ComposeServer {
+    pub name: String,
+    pub proxies: HashMap<String, Location>,
+    pub websockets: Vec<WebSocket>,
+}
+
+/// Get the published host port of a port mapping in the short or long syntax.
+/// Returns None for UDP ports and for ports that are only exposed to the containers.
+fn published_port(port: &Value) -> Option<u16> {
+    match port {
+        Value::String(s) => {
+            let (mapping, protocol) = s.split_once('/').unwrap_or((s, "tcp"));
+            if protocol != "tcp" {
+                return None;
+            }
+            // [host_ip:]host_port:container_port, the host port can also be a range
+            let parts: Vec<&str> = mapping.rsplitn(3, ':').collect();
+            if parts.len() < 2 {
+                return None;
+            }
+            parts[1].split('-').next().unwrap().parse().ok()
+        }
+        Value::Mapping(m) => {
+            let protocol = m.get(&Value::from("protocol"));
+            if protocol.is_some() && protocol != Some(&Value::from("tcp")) {
+                return None;
+            }
+            match m.get(&Value::from("published"))? {
+                Value::Number(n) => n.as_u64().and_then(|n| u16::try_from(n).ok()),
+                Value::String(s) => s.split('-').next().unwrap().parse().ok(),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Parse the compose file and group the services with published ports into servers.
+/// By default each service is its own server, unless they share the same x-localdev hostname,
+/// with single_server all the services are routed by path under that server.
+pub fn load(path: &Path, single_server: Option<&str>) -> Result<Vec<ComposeServer>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let compose: ComposeFile = serde_yaml::from_str(&contents)
+        .map_err(|e| format!("Invalid compose file {}: {}", path.display(), e))?;
+
+    let mut servers: BTreeMap<String, ComposeServer> = BTreeMap::new();
+    for (service_name, service) in compose.services {
+        let ext = service.localdev.unwrap_or_default();
+        let ports: Vec<u16> = service.ports.iter().filter_map(published_port).collect();
+        let port = match ext.port {
+            Some(p) if ports.contains(&p) => p,
+            Some(p) => {
+                return Err(format!(
+                    "Service {} does not publish the x-localdev port {}",
+                    service_name, p
+                ))
+            }
+            None => match ports.first() {
+                Some(p) => *p,
+                None => continue,
+            },
+        };
+
+        let name = crate::localdev_name(match single_server {
+            Some(s) => s,
+            None => ext.hostname.as_deref().unwrap_or(&service_name),
+        });
+        let mut location = match (&ext.path, single_server) {
+            (Some(p), _) => p.trim().to_string(),
+            (None, Some(_)) => format!("/{}", service_name),
+            (None, None) => String::from("/"),
+        };
+        if !location.starts_with('/') {
+            location = format!("/{}", location);
+        }
+
+        let server = servers
+            .entry(name.clone())
+            .or_insert_with(|| ComposeServer {
+                name,
+                proxies: HashMap::new(),
+                websockets: vec![],
+            });
+        if server.proxies.contains_key(&location) {
+            return Err(format!(
+                "Service {} uses the location {} already proxied in {}",
+                service_name, location, server.name
+            ));
+        }
+        server.proxies.insert(
+            location,
+            Location::new(Target::Proxy(format!("http://localhost:{}", port))),
+        );
+
+        if let Some(ws) = ext.websocket {
+            // a websocket location alone goes to the service port
+            let (location, target) = if ws.contains(':') || ws.contains('=') {
+                match crate::parse_proxy_arg(&ws, false) {
+                    Some(parsed) => parsed,
+                    None => continue,
+                }
+            } else {
+                (ws, format!("localhost:{}", port))
+            };
+            if server.websockets.iter().any(|w| w.location == location) {
+                return Err(format!(
+                    "Service {} uses the websocket location {} already proxied in {}",
+                    service_name, location, server.name
+                ));
+            }
+            server.websockets.push(WebSocket {
+                location,
+                target,
+                upstream: true,
+            });
+        }
+    }
+    Ok(servers.into_values().collect())
+}
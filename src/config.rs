@@ -0,0 +1,2381 @@
+use crate::logs;
+use crate::nginx::Capabilities;
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// Comment at the top of the config files imported from another file, followed by its path
+pub const IMPORT_MARKER: &str = "# localdev-import:";
+
+/// What a location serves
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    /// Proxy to the given URL
+    Proxy(String),
+    /// Serve the files of a directory
+    Static(StaticDir),
+    /// Pass to a FastCGI server like PHP-FPM, the scripts are found in the document root
+    FastCgi { pass: String, root: String },
+    /// Pass to a uwsgi server
+    Uwsgi(String),
+    /// Pass to a gRPC server like grpc://localhost:50051, or proxy gRPC-Web to a server handling it
+    Grpc { url: String, web: bool },
+    /// Proxy to several servers through an upstream
+    Balanced(Upstream),
+    /// Return a canned response
+    Stub(Stub),
+}
+
+/// A canned response, like fixed JSON while the backend is being built
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stub {
+    pub status: u16,
+    /// Set for the bodies, eg: application/json
+    pub content_type: Option<String>,
+    pub body: StubBody,
+}
+
+/// The body of a stub
+#[derive(Debug, Clone, PartialEq)]
+pub enum StubBody {
+    Empty,
+    /// A text body, or the URL of a redirect
+    Inline(String),
+    /// The path of a file served as the body
+    File(String),
+}
+
+/// How an upstream chooses its server
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+pub enum Balance {
+    RoundRobin,
+    IpHash,
+    LeastConn,
+}
+
+impl fmt::Display for Balance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Balance::RoundRobin => write!(f, "round-robin"),
+            Balance::IpHash => write!(f, "ip_hash"),
+            Balance::LeastConn => write!(f, "least_conn"),
+        }
+    }
+}
+
+/// A server of an upstream
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamServer {
+    /// The address, eg: localhost:8080
+    pub address: String,
+    pub weight: Option<u32>,
+    /// Only used when the other servers are down
+    pub backup: bool,
+}
+
+impl fmt::Display for UpstreamServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.address)?;
+        if let Some(weight) = self.weight {
+            write!(f, "*{}", weight)?;
+        }
+        if self.backup {
+            write!(f, "+backup")?;
+        }
+        Ok(())
+    }
+}
+
+/// The servers of a proxy target balancing the requests between them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upstream {
+    /// http or https
+    pub scheme: String,
+    pub servers: Vec<UpstreamServer>,
+    pub balance: Balance,
+}
+
+impl Upstream {
+    /// The host name of the servers, sent with SNI instead of the name of the upstream,
+    /// the https servers all have the same one
+    fn host(&self) -> &str {
+        self.servers
+            .first()
+            .map_or("", |s| address_host(&s.address))
+    }
+}
+
+/// The host of a server address, eg: localhost for localhost:8080 or ::1 for [::1]:8080
+pub fn address_host(address: &str) -> &str {
+    let host = match address.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        _ => address,
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Start of the name of the upstreams of the locations, followed by the server name and location
+const UPSTREAM_PREFIX: &str = "localdev-";
+
+/// The name of the upstream of a location
+fn upstream_name(name: &str, path: &str) -> String {
+    let slug: String = path
+        .trim_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    if slug.is_empty() {
+        format!("{}{}", UPSTREAM_PREFIX, name)
+    } else {
+        format!("{}{}-{}", UPSTREAM_PREFIX, name, slug)
+    }
+}
+
+/// Start of the proxy_pass URL to a unix socket, followed by the socket path, a colon and the URI
+const UNIX_PROXY_PREFIX: &str = "http://unix:";
+
+impl Target {
+    /// Make the target of a proxy argument according to its scheme:
+    /// fcgi://127.0.0.1:9000 and fcgi+unix:/run/php-fpm.sock with the given document root,
+    /// uwsgi://127.0.0.1:3031 and uwsgi+unix:/run/uwsgi.sock, else a HTTP proxy
+    /// which can also be to a unix socket like unix:/run/gunicorn.sock.
+    pub fn from_url(url: &str, root: &str) -> Target {
+        if let Some(pass) = url.strip_prefix("fcgi://") {
+            Target::FastCgi {
+                pass: pass.trim_end_matches('/').to_owned(),
+                root: root.to_owned(),
+            }
+        } else if let Some(socket) = url.strip_prefix("fcgi+unix:") {
+            Target::FastCgi {
+                pass: format!("unix:{}", socket),
+                root: root.to_owned(),
+            }
+        } else if let Some(pass) = url.strip_prefix("uwsgi://") {
+            Target::Uwsgi(pass.trim_end_matches('/').to_owned())
+        } else if let Some(socket) = url.strip_prefix("uwsgi+unix:") {
+            Target::Uwsgi(format!("unix:{}", socket))
+        } else if url.starts_with("grpc://") || url.starts_with("grpcs://") {
+            Target::Grpc {
+                url: url.trim_end_matches('/').to_owned(),
+                web: false,
+            }
+        } else if let Some(socket) = url.strip_prefix("unix:") {
+            // the socket path is followed by the URI, eg: unix:/run/app.sock:/api
+            if socket.contains(':') {
+                Target::Proxy(format!("{}{}", UNIX_PROXY_PREFIX, socket))
+            } else {
+                Target::Proxy(format!("{}{}:", UNIX_PROXY_PREFIX, socket))
+            }
+        } else {
+            Target::Proxy(url.to_owned())
+        }
+    }
+
+    /// The command likely starting the backend of the target, guessed from its scheme and port
+    fn start_command(&self) -> Option<String> {
+        if let Some(socket) = self.socket() {
+            return Some(match self {
+                Target::FastCgi { .. } => String::from("php-fpm"),
+                Target::Uwsgi(_) => format!("uwsgi --socket {} --module app", socket),
+                _ => format!("gunicorn --bind unix:{} app:app", socket),
+            });
+        }
+        match self {
+            Target::Proxy(url) => {
+                let port = url
+                    .split_once("://")
+                    .and_then(|(_, rest)| rest.split('/').next())
+                    .and_then(|host| host.rsplit_once(':'))
+                    .and_then(|(_, port)| port.parse::<u16>().ok())?;
+                Some(match port {
+                    4200 => String::from("ng serve"),
+                    5173 => String::from("npm run dev"),
+                    4000 => String::from("mix phx.server"),
+                    8000 => String::from("python manage.py runserver"),
+                    _ => format!("PORT={} npm run dev", port),
+                })
+            }
+            Target::FastCgi { .. } => Some(String::from("php-fpm")),
+            Target::Uwsgi(pass) => Some(format!("uwsgi --socket {} --module app", pass)),
+            Target::Static(_) | Target::Grpc { .. } | Target::Balanced(_) | Target::Stub(_) => None,
+        }
+    }
+
+    /// The path of the unix socket the target passes to, if any
+    pub fn socket(&self) -> Option<&str> {
+        match self {
+            Target::Proxy(url) => url
+                .strip_prefix(UNIX_PROXY_PREFIX)
+                .map(|s| s.split(':').next().unwrap()),
+            Target::FastCgi { pass, .. } | Target::Uwsgi(pass) => pass.strip_prefix("unix:"),
+            Target::Static(_) | Target::Grpc { .. } | Target::Balanced(_) | Target::Stub(_) => None,
+        }
+    }
+
+    /// Check if the target is reached over TLS, like https://, grpcs:// or balanced over https
+    pub fn is_tls(&self) -> bool {
+        match self {
+            Target::Proxy(url) => url.starts_with("https://"),
+            Target::Grpc { url, .. } => url.starts_with("grpcs://"),
+            Target::Balanced(u) => u.scheme == "https",
+            _ => false,
+        }
+    }
+}
+
+/// Check if a proxy URL has an URI, eg: http://localhost:8080/ but not http://localhost:8080
+fn has_uri(url: &str) -> bool {
+    match url.strip_prefix(UNIX_PROXY_PREFIX) {
+        Some(s) => s.split_once(':').is_some_and(|(_, uri)| !uri.is_empty()),
+        None => url
+            .split_once("://")
+            .is_some_and(|(_, rest)| rest.contains('/')),
+    }
+}
+
+/// Give the URL form of a fastcgi_pass or uwsgi_pass address, eg: fcgi+unix:/run/php-fpm.sock
+fn pass_url(scheme: &str, pass: &str) -> String {
+    match pass.strip_prefix("unix:") {
+        Some(socket) => format!("{}+unix:{}", scheme, socket),
+        None => format!("{}://{}", scheme, pass),
+    }
+}
+
+/// A directory of static files
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StaticDir {
+    pub dir: String,
+    /// Fall back to the index.html of the location for a SPA
+    pub spa: bool,
+    /// List the directory contents
+    pub autoindex: bool,
+    /// Value of the expires directive for the cache headers, eg: 1h or epoch to disable caching
+    pub expires: Option<String>,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Proxy(url) => match url.strip_prefix(UNIX_PROXY_PREFIX) {
+                // show the socket like it was given
+                Some(s) => {
+                    let (socket, uri) = s.split_once(':').unwrap_or((s, ""));
+                    if uri.trim_matches('/').is_empty() {
+                        write!(f, "unix:{}", socket)
+                    } else {
+                        write!(f, "unix:{}:{}", socket, uri.trim_end_matches('/'))
+                    }
+                }
+                None => write!(f, "{}", url.trim_end_matches('/')),
+            },
+            Target::Static(s) => write!(f, "{}", s.dir.trim_end_matches('/')),
+            Target::FastCgi { pass, .. } => write!(f, "{}", pass_url("fcgi", pass)),
+            Target::Uwsgi(pass) => write!(f, "{}", pass_url("uwsgi", pass)),
+            Target::Grpc { url, .. } => write!(f, "{}", url),
+            Target::Balanced(u) => {
+                let servers: Vec<String> = u.servers.iter().map(|s| s.to_string()).collect();
+                write!(f, "{}://{}", u.scheme, servers.join(","))
+            }
+            Target::Stub(stub) => {
+                write!(f, "{}", stub.status)?;
+                if let Some(content_type) = &stub.content_type {
+                    write!(f, " {}", content_type)?;
+                }
+                match &stub.body {
+                    StubBody::Empty => Ok(()),
+                    // shown on one line
+                    StubBody::Inline(text) => write!(f, " {}", text.replace('\n', "\\n")),
+                    StubBody::File(path) => write!(f, " {}", path),
+                }
+            }
+        }
+    }
+}
+
+/// TLS settings to a https proxy target
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UpstreamSsl {
+    /// CA file to verify the upstream certificate with, it is not verified without one
+    pub verify_ca: Option<String>,
+    /// Client certificate and key files for an upstream requiring them
+    pub client_cert: Option<(String, String)>,
+}
+
+/// How a location matches the request path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Match {
+    /// The paths starting with the location
+    Prefix,
+    /// Only the location path
+    Exact,
+    /// The paths matching the location regex
+    Regex,
+}
+
+/// Comment in a location proxying gRPC-Web
+const GRPC_WEB_MARKER: &str = "# localdev-grpc-web";
+
+/// Comment in a location with CORS, followed by its allowed origins
+const CORS_MARKER: &str = "# localdev-cors:";
+
+/// Cross-origin access to a location, the allowed request origins are reflected with credentials
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cors {
+    /// Origins like https://app.localdev, *.localdev for all the local servers or * for any origin
+    pub origins: Vec<String>,
+}
+
+impl Cors {
+    /// The regex matching the allowed origins
+    fn origin_regex(&self) -> String {
+        let origins: Vec<String> = self
+            .origins
+            .iter()
+            .map(|o| match o.as_str() {
+                "*" => String::from(".+"),
+                "*.localdev" => String::from(r"https?://[^/]+\.localdev(:[0-9]+)?"),
+                _ => regex::escape(o),
+            })
+            .collect();
+        format!("^({})$", origins.join("|"))
+    }
+}
+
+/// A location of a server, what it serves and how
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub target: Target,
+    pub matching: Match,
+    /// Remove the location prefix from the path given to a proxy target
+    pub strip_prefix: bool,
+    /// Redirect the path without a trailing slash to the location, else it is also served
+    pub bare_redirect: bool,
+    pub upstream_ssl: Option<UpstreamSsl>,
+    /// Headers added to the request given to a proxy target
+    pub request_headers: Vec<(String, String)>,
+    /// Headers added to the response
+    pub response_headers: Vec<(String, String)>,
+    pub cors: Option<Cors>,
+    pub throttle: Throttle,
+    pub tuning: Tuning,
+    /// Stream the responses of a proxy target over HTTP/1.1, like Server-Sent Events
+    pub streaming: bool,
+    /// Position in the config file, nginx tries the regex locations in this order
+    pub position: usize,
+}
+
+/// The body size, timeouts and buffering of a location, the nginx defaults when not set
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Tuning {
+    /// The largest request body, eg: 100m or 0 for no limit
+    pub max_body_size: Option<String>,
+    /// The timeout between two reads of the response of the target, eg: 1h
+    pub read_timeout: Option<String>,
+    /// The timeout between two writes of the request to the target
+    pub send_timeout: Option<String>,
+    /// Buffering of the responses of the target, off for streaming
+    pub buffering: Option<bool>,
+}
+
+/// How a location is slowed down to reproduce a slow network
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Throttle {
+    /// The bandwidth of each response, eg: 50k bytes per second
+    pub rate: Option<String>,
+    /// The requests allowed per client, eg: 2r/s, with the burst of requests delayed to that rate
+    pub requests: Option<(String, Option<u32>)>,
+    /// The connections allowed at the same time per client
+    pub connections: Option<u32>,
+}
+
+impl Location {
+    pub fn new(target: Target) -> Location {
+        Location {
+            target,
+            matching: Match::Prefix,
+            strip_prefix: true,
+            bare_redirect: true,
+            upstream_ssl: None,
+            request_headers: vec![],
+            response_headers: vec![],
+            cors: None,
+            throttle: Throttle::default(),
+            tuning: Tuning::default(),
+            streaming: false,
+            position: 0,
+        }
+    }
+
+    /// Add a header, replacing the header with the same name
+    pub fn set_header(&mut self, name: &str, value: &str, response: bool) {
+        let headers = if response {
+            &mut self.response_headers
+        } else {
+            &mut self.request_headers
+        };
+        headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        headers.push((name.to_owned(), value.to_owned()));
+    }
+
+    /// Make a location back from the directives read in its block, None if it has no target
+    fn from_directives(matching: Match, directives: &[(String, String)]) -> Option<Location> {
+        let get = |name: &str| {
+            directives
+                .iter()
+                .rev()
+                .find(|(d, _)| d == name)
+                .map(|(_, v)| v.trim_matches('"').to_owned())
+        };
+        let target = if let Some(url) = get("grpc_pass") {
+            Target::Grpc { url, web: false }
+        } else if let (Some(url), Some(_)) = (get("proxy_pass"), get(GRPC_WEB_MARKER)) {
+            // the gRPC-Web requests are proxied over HTTP
+            Target::Grpc {
+                url: url.replacen("http", "grpc", 1),
+                web: true,
+            }
+        } else if let Some(url) = get("proxy_pass") {
+            Target::Proxy(url)
+        } else if let Some(pass) = get("fastcgi_pass") {
+            Target::FastCgi {
+                pass,
+                root: get("root").unwrap_or_default(),
+            }
+        } else if let Some(pass) = get("uwsgi_pass") {
+            Target::Uwsgi(pass)
+        } else if let Some((_, value)) = directives.iter().rev().find(|(d, _)| d == "return") {
+            let (status, text) = value.split_once(' ').unwrap_or((value, ""));
+            let text = text.trim();
+            Target::Stub(Stub {
+                status: status.parse().ok()?,
+                // the types are cleared so the content type is not guessed from the path
+                content_type: get("types").and_then(|_| get("default_type")),
+                body: if text.is_empty() {
+                    StubBody::Empty
+                } else {
+                    StubBody::Inline(unquote_body(text))
+                },
+            })
+        } else if let (Some(path), Some(_)) = (get("alias"), get("types")) {
+            Target::Stub(Stub {
+                status: 200,
+                content_type: get("default_type"),
+                body: StubBody::File(path),
+            })
+        } else if let Some(dir) = get("root").or_else(|| get("alias")) {
+            Target::Static(StaticDir {
+                dir,
+                // a fallback on index.html is for a SPA
+                spa: get("try_files").is_some_and(|v| v.ends_with("/index.html")),
+                autoindex: get("autoindex").as_deref() == Some("on"),
+                expires: get("expires"),
+            })
+        } else {
+            return None;
+        };
+
+        // the TLS, timeouts and buffering are for the module passing to the target
+        let pass = |name: &str| pass_module(&target).and_then(|m| get(&format!("{}_{}", m, name)));
+        let upstream_ssl = pass("ssl_server_name").map(|_| UpstreamSsl {
+            verify_ca: match pass("ssl_verify").as_deref() {
+                Some("on") => pass("ssl_trusted_certificate"),
+                _ => None,
+            },
+            client_cert: pass("ssl_certificate").zip(pass("ssl_certificate_key")),
+        });
+
+        // a proxy URL with an URI replaces the location prefix
+        let strip_prefix = match &target {
+            Target::Proxy(url) => has_uri(url),
+            _ => true,
+        };
+
+        let cors = get(CORS_MARKER).map(|origins| Cors {
+            origins: origins.split(',').map(|o| o.trim().to_owned()).collect(),
+        });
+
+        // a streaming proxy keeps the connection to its target without the Connection header
+        let streaming = get("proxy_http_version").as_deref() == Some("1.1")
+            && directives.iter().any(|(d, v)| {
+                d == "proxy_set_header"
+                    && parse_header(v) == (String::from("Connection"), String::new())
+            });
+
+        let grpc_web = matches!(target, Target::Grpc { web: true, .. });
+        let headers = |name: &str| {
+            directives
+                .iter()
+                .filter(|(d, _)| d == name)
+                .map(|(_, v)| parse_header(v))
+                // the streaming headers are written from its mode
+                .filter(|(n, _)| !streaming || n != "Connection" && n != "X-Accel-Buffering")
+                // the CORS headers are written from its origins
+                .filter(|(n, v)| {
+                    cors.is_none()
+                        || !(n.starts_with("Access-Control-") || n == "Vary" && v == "Origin")
+                })
+                // and the gRPC-Web ones from its target
+                .filter(|(n, _)| !grpc_web || n != "Access-Control-Expose-Headers")
+                .collect()
+        };
+
+        let request_headers = match target {
+            Target::Grpc { web: false, .. } => headers("grpc_set_header"),
+            _ => headers("proxy_set_header"),
+        };
+        // the rate of the requests is in their zone, which is resolved at the end
+        let throttle = Throttle {
+            rate: get("limit_rate"),
+            requests: get("limit_req").map(|v| {
+                let mut zone = String::new();
+                let mut burst = None;
+                for p in v.split_whitespace() {
+                    if let Some(z) = p.strip_prefix("zone=") {
+                        zone = z.to_owned();
+                    } else if let Some(b) = p.strip_prefix("burst=") {
+                        burst = b.parse().ok();
+                    }
+                }
+                (zone, burst)
+            }),
+            connections: get("limit_conn").and_then(|v| v.split_whitespace().nth(1)?.parse().ok()),
+        };
+
+        let tuning = Tuning {
+            max_body_size: get("client_max_body_size"),
+            read_timeout: pass("read_timeout"),
+            send_timeout: pass("send_timeout"),
+            // gRPC-Web is always proxied without buffering
+            buffering: match target {
+                Target::Grpc { web: true, .. } => None,
+                _ => pass("buffering").map(|b| b == "on"),
+            },
+        };
+
+        Some(Location {
+            target,
+            matching,
+            strip_prefix,
+            bare_redirect: true,
+            upstream_ssl,
+            request_headers,
+            response_headers: headers("add_header"),
+            cors,
+            throttle,
+            tuning,
+            streaming,
+            position: 0,
+        })
+    }
+
+    /// The options of the location shown next to its target
+    fn notes(&self) -> Vec<String> {
+        let mut notes = vec![];
+        match &self.target {
+            Target::Static(s) => {
+                if s.spa {
+                    notes.push(String::from("spa"));
+                }
+                if s.autoindex {
+                    notes.push(String::from("autoindex"));
+                }
+                if let Some(expires) = &s.expires {
+                    notes.push(format!("expires {}", expires));
+                }
+            }
+            Target::FastCgi { root, .. } => notes.push(format!("root {}", root)),
+            Target::Grpc { web: true, .. } => notes.push(String::from("grpc-web")),
+            Target::Balanced(u) => notes.push(format!("{} upstream", u.balance)),
+            _ => (),
+        }
+        if let Target::Proxy(_) | Target::Balanced(_) = self.target {
+            if !self.strip_prefix && self.matching != Match::Regex {
+                notes.push(String::from("full path"));
+            }
+        }
+        match self.matching {
+            Match::Exact => notes.push(String::from("exact")),
+            Match::Regex => notes.push(String::from("regex")),
+            Match::Prefix if !self.bare_redirect => notes.push(String::from("no redirect")),
+            Match::Prefix => (),
+        }
+        for (name, _) in self.request_headers.iter() {
+            notes.push(format!("+{}", name));
+        }
+        for (name, _) in self.response_headers.iter() {
+            notes.push(format!("+{} in response", name));
+        }
+        if let Some(cors) = &self.cors {
+            notes.push(format!("cors {}", cors.origins.join(" ")));
+        }
+        if let Some(rate) = &self.throttle.rate {
+            notes.push(format!("{}/s", rate));
+        }
+        match &self.throttle.requests {
+            Some((rate, Some(burst))) => notes.push(format!("{} burst {}", rate, burst)),
+            Some((rate, None)) => notes.push(rate.to_owned()),
+            None => (),
+        }
+        if let Some(connections) = self.throttle.connections {
+            notes.push(format!("{} connections", connections));
+        }
+        if let Some(size) = &self.tuning.max_body_size {
+            notes.push(format!("max body {}", size));
+        }
+        if let Some(timeout) = &self.tuning.read_timeout {
+            notes.push(format!("read timeout {}", timeout));
+        }
+        if let Some(timeout) = &self.tuning.send_timeout {
+            notes.push(format!("send timeout {}", timeout));
+        }
+        if self.streaming {
+            notes.push(String::from("streaming"));
+        }
+        // gRPC has no buffering to turn off, and the streaming locations are never buffered
+        let buffered = !self.streaming && !matches!(self.target, Target::Grpc { .. });
+        if self.tuning.buffering == Some(false) && buffered {
+            notes.push(String::from("no buffering"));
+        }
+        if let Some(ssl) = &self.upstream_ssl {
+            match &ssl.verify_ca {
+                Some(ca) => notes.push(format!("verify {}", ca)),
+                None => notes.push(String::from("no verify")),
+            }
+            if ssl.client_cert.is_some() {
+                notes.push(String::from("client cert"));
+            }
+        }
+        notes
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.target)?;
+        let notes = self.notes();
+        if !notes.is_empty() {
+            write!(f, " ({})", notes.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// How a server answers on HTTP
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+pub enum HttpMode {
+    /// Serve the locations on both HTTP and HTTPS
+    Both,
+    /// Redirect HTTP to HTTPS
+    Redirect,
+    /// Only listen on HTTPS
+    HttpsOnly,
+}
+
+/// The options of a whole server
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerOptions {
+    pub http: HttpMode,
+    /// Also listen with HTTP/3 on QUIC
+    pub http3: bool,
+    /// The QUIC listener has the reuseport parameter, which is only allowed once for all the servers
+    pub quic_reuseport: bool,
+    /// The htpasswd file of the users allowed with basic auth
+    pub basic_auth: Option<String>,
+    /// The CA file the client certificates must be signed by
+    pub client_ca: Option<String>,
+    /// The directory of the access and error logs of the server
+    pub logs: Option<String>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            http: HttpMode::Both,
+            http3: false,
+            quic_reuseport: false,
+            basic_auth: None,
+            client_ca: None,
+            logs: None,
+        }
+    }
+}
+
+/// The server names and locations read from a config file
+#[derive(Debug, Default)]
+pub struct ParsedConfig {
+    pub server_names: Vec<String>,
+    pub locations: HashMap<String, Location>,
+    pub websockets: Vec<WebSocket>,
+    /// The file the server was imported from
+    pub source: Option<String>,
+    pub options: ServerOptions,
+}
+
+/// Start of the name of the websocket upstream, followed by the server name
+const WS_UPSTREAM_PREFIX: &str = "ws-backend-";
+
+/// A websocket proxy of a server
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebSocket {
+    pub location: String,
+    /// The address proxied to, eg: localhost:3000 or unix:/run/app.sock
+    pub target: String,
+    /// Proxy through an upstream keeping each client on the same server, else directly
+    pub upstream: bool,
+}
+
+impl WebSocket {
+    /// The name of its upstream, the /ws location has the name used before there could be several
+    fn upstream_name(&self, name: &str) -> String {
+        match self.location.trim_matches('/') {
+            "ws" => format!("{}{}", WS_UPSTREAM_PREFIX, name),
+            l => format!("{}{}-{}", WS_UPSTREAM_PREFIX, name, l.replace('/', "-")),
+        }
+    }
+
+    /// The URL it is proxied to
+    fn pass_url(&self, name: &str) -> String {
+        if self.upstream {
+            return format!("http://{}", self.upstream_name(name));
+        }
+        match Target::from_url(&self.target, "") {
+            Target::Proxy(url) if url.starts_with(UNIX_PROXY_PREFIX) => url,
+            _ => format!("http://{}", self.target),
+        }
+    }
+
+    /// The location shown in the list of the server locations
+    pub fn listing(&self) -> Location {
+        let target = if self.target.starts_with("unix:") {
+            Target::from_url(&self.target, "")
+        } else {
+            Target::Proxy(format!("ws://{}", self.target))
+        };
+        Location::new(target)
+    }
+}
+
+/// Get the value of a directive line, eg: `root /var/www;` gives `/var/www`
+fn directive_value(line: &str) -> &str {
+    line.split_once(char::is_whitespace)
+        .map(|(_, v)| v)
+        .unwrap_or("")
+        .trim()
+        .trim_end_matches(';')
+        .trim()
+}
+
+/// Get the name and value of a header directive, eg: `X-Forwarded-Proto "https" always`
+fn parse_header(value: &str) -> (String, String) {
+    let (name, value) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+    let value = value.trim();
+    let value = value.strip_suffix(" always").unwrap_or(value).trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value);
+    (name.to_owned(), value.replace("\\\"", "\""))
+}
+
+/// Quote a header value for nginx
+fn quote_value(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\\\""))
+}
+
+/// Quote the body of a stub for nginx, on one line
+fn quote_body(body: &str) -> String {
+    let body = body
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    format!("\"{}\"", body)
+}
+
+/// Read the body of a stub quoted for nginx
+fn unquote_body(value: &str) -> String {
+    let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(v) => v,
+        None => return value.to_owned(),
+    };
+    let mut body = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            body.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => body.push('\n'),
+            Some('r') => body.push('\r'),
+            Some('t') => body.push('\t'),
+            Some(c) => body.push(c),
+            None => body.push('\\'),
+        }
+    }
+    body
+}
+
+/// Count the blocks opened minus the ones closed on a line, the braces of the quoted strings
+/// like the bodies of the stubs are not counted
+fn block_depth(line: &str) -> i32 {
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            '{' if !quoted => depth += 1,
+            '}' if !quoted => depth -= 1,
+            _ => (),
+        }
+    }
+    depth
+}
+
+/// Quote a path for nginx if it contains spaces
+fn quote_path(path: &str) -> String {
+    if path.contains(char::is_whitespace) {
+        format!("\"{}\"", path)
+    } else {
+        path.to_owned()
+    }
+}
+
+/// Split the modifier from the path of a location, eg: `= /api` matches exactly /api
+fn parse_location_path(location: &str) -> (Match, String) {
+    let location = location.trim();
+    if let Some(path) = location.strip_prefix("= ") {
+        (Match::Exact, path.trim().to_owned())
+    } else if let Some(path) = location.strip_prefix("~ ") {
+        (Match::Regex, path.trim().to_owned())
+    } else {
+        (Match::Prefix, location.to_owned())
+    }
+}
+
+/// Find the location block of a line of a config file (counted from 1), as written after
+/// location, eg: /api/ or = /health
+pub fn location_at(contents: &str, line: usize) -> Option<String> {
+    let location_rx = Regex::new(r"^\s*location\s+(.*?)\s*\{").unwrap();
+    // the blocks opened before the line, with the path of the locations
+    let mut blocks: Vec<Option<String>> = vec![];
+    for l in contents.lines().take(line) {
+        let depth = block_depth(l);
+        if depth > 0 {
+            let location = location_rx.captures(l).map(|c| c[1].to_owned());
+            blocks.push(location);
+            blocks.extend((1..depth).map(|_| None));
+        }
+        for _ in depth..0 {
+            blocks.pop();
+        }
+    }
+    blocks.into_iter().rev().flatten().next()
+}
+
+/// Parse a config file for its server names and what each location serves
+pub fn parse_config(contents: &str, verbose: bool) -> ParsedConfig {
+    let server_name_rx = Regex::new(r"server_name\s+(.*)\s*;").unwrap();
+    let location_rx = Regex::new(r"location\s+(.*)\s*\{").unwrap();
+
+    let mut parsed = ParsedConfig::default();
+    // the current location and the directives read in its block
+    let mut location: Option<(String, Vec<(String, String)>)> = None;
+    let mut depth = 0;
+    // the upstreams with how they balance and their servers
+    let mut upstreams: HashMap<String, (Balance, Vec<UpstreamServer>)> = HashMap::new();
+    let mut in_upstream: Option<String> = None;
+    // the rate of the request zones
+    let mut request_zones: HashMap<String, String> = HashMap::new();
+    let mut listen_http = false;
+    let mut redirect_http = false;
+    // the position of the next location in the current server
+    let mut position = 0;
+    for line in contents.lines() {
+        let line = line.trim_start();
+        if line.starts_with("server {") {
+            position = 0;
+        }
+        if line.starts_with("listen 80") {
+            listen_http = true;
+        } else if line.starts_with("listen 443 quic") {
+            parsed.options.http3 = true;
+            parsed.options.quic_reuseport = line.contains("reuseport");
+        } else if line.starts_with("return 301 https://") && location.is_none() {
+            redirect_http = true;
+        } else if line.starts_with("auth_basic_user_file") {
+            parsed.options.basic_auth = Some(directive_value(line).to_owned());
+        } else if line.starts_with("ssl_client_certificate") {
+            parsed.options.client_ca = Some(directive_value(line).to_owned());
+        } else if line.starts_with("access_log") {
+            // the log file is followed by the format
+            let file = directive_value(line).rsplit_once(' ').map(|(f, _)| f);
+            parsed.options.logs = file
+                .and_then(|f| Path::new(f.trim_matches('"')).parent())
+                .map(|dir| dir.display().to_string());
+        }
+        // the upstreams of the websockets and locations
+        if let Some(upstream) = line.strip_prefix("upstream") {
+            let upstream = upstream.trim().trim_end_matches('{').trim().to_owned();
+            upstreams.insert(upstream.to_owned(), (Balance::RoundRobin, vec![]));
+            in_upstream = Some(upstream);
+        } else if let Some(upstream) = &in_upstream {
+            let (balance, servers) = upstreams.get_mut(upstream).unwrap();
+            let mut parts = directive_value(line).split_whitespace();
+            if line.starts_with("server") {
+                let address = parts.next().unwrap_or_default().to_owned();
+                let mut server = UpstreamServer {
+                    address,
+                    weight: None,
+                    backup: false,
+                };
+                for p in parts {
+                    match p.strip_prefix("weight=") {
+                        Some(w) => server.weight = w.parse().ok(),
+                        None => server.backup |= p == "backup",
+                    }
+                }
+                servers.push(server);
+            } else if line.starts_with("ip_hash") {
+                *balance = Balance::IpHash;
+            } else if line.starts_with("least_conn") {
+                *balance = Balance::LeastConn;
+            } else if line.starts_with('}') {
+                in_upstream = None;
+            }
+        }
+        if line.starts_with("limit_req_zone") {
+            let mut parts = directive_value(line).split_whitespace();
+            let zone = parts.find_map(|p| p.strip_prefix("zone="));
+            let rate = parts.find_map(|p| p.strip_prefix("rate="));
+            if let (Some((zone, _)), Some(rate)) = (zone.and_then(|z| z.split_once(':')), rate) {
+                request_zones.insert(zone.to_owned(), rate.to_owned());
+            }
+        }
+        if let Some(s) = line.strip_prefix(IMPORT_MARKER) {
+            parsed.source = Some(s.trim().to_owned());
+        }
+        if line.starts_with("server_name") {
+            let caps = server_name_rx.captures(line);
+            if let Some(c) = caps {
+                parsed
+                    .server_names
+                    .push(c.get(1).unwrap().as_str().to_owned());
+            }
+        }
+        if line.starts_with("location") {
+            let caps = location_rx.captures(line);
+            if let Some(c) = caps {
+                location = Some((c.get(1).unwrap().as_str().to_owned(), vec![]));
+                depth = 0;
+            }
+        }
+        if let Some((l, directives)) = &mut location {
+            depth += block_depth(line);
+            if depth <= 0 {
+                // end of the location block
+                let (matching, path) = parse_location_path(l);
+                match Location::from_directives(matching, directives) {
+                    // the error page is written with the server
+                    _ if path == ERROR_PAGE => (),
+                    // the websocket proxy passes to its upstream, which is resolved at the end
+                    Some(Location {
+                        target: Target::Proxy(url),
+                        ..
+                    }) if url.starts_with(&format!("http://{}", WS_UPSTREAM_PREFIX)) => {
+                        parsed.websockets.push(WebSocket {
+                            location: path,
+                            target: url.trim_start_matches("http://").to_owned(),
+                            upstream: true,
+                        });
+                    }
+                    // or directly with the Upgrade header
+                    Some(Location {
+                        target: Target::Proxy(url),
+                        request_headers,
+                        ..
+                    }) if request_headers.iter().any(|(n, _)| n == "Upgrade") => {
+                        let target = match url.strip_prefix(UNIX_PROXY_PREFIX) {
+                            Some(socket) => format!("unix:{}", socket.trim_end_matches(':')),
+                            None => url.trim_start_matches("http://").to_owned(),
+                        };
+                        parsed.websockets.push(WebSocket {
+                            location: path,
+                            target,
+                            upstream: false,
+                        });
+                    }
+                    Some(mut loc) => {
+                        // the SSL server, written last, has all the locations in their order
+                        loc.position = position;
+                        position += 1;
+                        parsed.locations.insert(path, loc);
+                    }
+                    None => {
+                        if verbose {
+                            println!("No target for location: {}", l);
+                        }
+                    }
+                }
+                location = None;
+            } else if let Some(origins) = line.strip_prefix(CORS_MARKER) {
+                directives.push((CORS_MARKER.to_owned(), origins.trim().to_owned()));
+            } else if line.starts_with(GRPC_WEB_MARKER) {
+                directives.push((GRPC_WEB_MARKER.to_owned(), String::new()));
+            } else if depth == 1
+                && !line.starts_with("location")
+                && !line.starts_with('}')
+                && !line.is_empty()
+            {
+                // the directives of the nested blocks are not the location ones
+                let directive = line
+                    .split(|c: char| c.is_whitespace() || c == ';')
+                    .next()
+                    .unwrap();
+                directives.push((directive.to_owned(), directive_value(line).to_owned()));
+            }
+        }
+    }
+
+    // both server blocks have the websocket locations
+    parsed
+        .websockets
+        .sort_by(|a, b| a.location.cmp(&b.location));
+    parsed.websockets.dedup_by(|a, b| a.location == b.location);
+    for ws in parsed.websockets.iter_mut().filter(|ws| ws.upstream) {
+        if let Some((_, servers)) = upstreams.get(&ws.target) {
+            ws.target = servers
+                .first()
+                .map(|s| s.address.to_owned())
+                .unwrap_or_default();
+        }
+    }
+    // the locations proxied to their upstream
+    for location in parsed.locations.values_mut() {
+        if let Some((zone, _)) = &mut location.throttle.requests {
+            *zone = request_zones.get(zone).cloned().unwrap_or_default();
+        }
+        let upstream = match &location.target {
+            Target::Proxy(url) => url.split_once("://").and_then(|(scheme, rest)| {
+                let host = rest.split('/').next().unwrap();
+                match upstreams.get(host) {
+                    Some((balance, servers)) if host.starts_with(UPSTREAM_PREFIX) => {
+                        Some(Upstream {
+                            scheme: scheme.to_owned(),
+                            servers: servers.clone(),
+                            balance: *balance,
+                        })
+                    }
+                    _ => None,
+                }
+            }),
+            _ => None,
+        };
+        if let Some(upstream) = upstream {
+            location.target = Target::Balanced(upstream);
+        }
+    }
+
+    parsed.options.http = match (listen_http, redirect_http) {
+        (false, _) => HttpMode::HttpsOnly,
+        (true, true) => HttpMode::Redirect,
+        (true, false) => HttpMode::Both,
+    };
+
+    // the Alt-Svc header is written from the HTTP/3 option
+    if parsed.options.http3 {
+        for l in parsed.locations.values_mut() {
+            l.response_headers.retain(|(n, _)| n != "Alt-Svc");
+        }
+    }
+
+    // an exact location for the path of a prefix location serves it without redirecting
+    let bare_paths: Vec<String> = parsed
+        .locations
+        .iter()
+        .filter(|(path, l)| {
+            l.matching == Match::Exact
+                && match parsed.locations.get(&format!("{}/", path)) {
+                    Some(prefix) => prefix.matching == Match::Prefix && prefix.target == l.target,
+                    None => false,
+                }
+        })
+        .map(|(path, _)| path.to_owned())
+        .collect();
+    for path in bare_paths {
+        parsed.locations.remove(&path);
+        if let Some(prefix) = parsed.locations.get_mut(&format!("{}/", path)) {
+            prefix.bare_redirect = false;
+        }
+    }
+
+    // the trailing slash of the prefix locations is added when writing them
+    parsed.locations = parsed
+        .locations
+        .into_iter()
+        .map(|(path, l)| match l.matching {
+            Match::Prefix if path != "/" => (path.trim_end_matches('/').to_owned(), l),
+            _ => (path, l),
+        })
+        .collect();
+    parsed
+}
+
+/// Write helper for the proxy location header
+fn write_location_header<T: std::io::Write>(
+    f: &mut BufWriter<T>,
+    location: &str,
+    is_websocket: bool,
+) {
+    f.write_all(b"  location ").unwrap();
+    if !location.starts_with('/') {
+        f.write_all(b"/").unwrap();
+    }
+    write!(f, "{}", location).unwrap();
+    if !is_websocket && !location.ends_with('/') {
+        f.write_all(b"/").unwrap();
+    }
+    f.write_all(b" {\n").unwrap();
+}
+
+/// Write helper for the header of a location according to how it matches
+fn write_match_header<T: std::io::Write>(f: &mut BufWriter<T>, path: &str, matching: Match) {
+    match matching {
+        Match::Prefix => write_location_header(f, path, false),
+        Match::Exact => writeln!(f, "  location = /{} {{", path.trim_start_matches('/')).unwrap(),
+        Match::Regex => writeln!(f, "  location ~ {} {{", path).unwrap(),
+    }
+}
+
+/// Write helper for the proxy section, without an URI the proxy gets the full path
+fn write_proxy<T: std::io::Write>(
+    f: &mut BufWriter<T>,
+    target: &str,
+    strip_prefix: bool,
+    matching: Match,
+) {
+    if !strip_prefix {
+        writeln!(f, "      proxy_pass {};", target.trim_end_matches('/')).unwrap();
+        return;
+    }
+    write!(f, "      proxy_pass {}", target).unwrap();
+    // an exact location is replaced by the whole URI
+    let exact_uri = matching == Match::Exact && has_uri(target);
+    if !target.ends_with('/') && !exact_uri {
+        f.write_all(b"/").unwrap();
+    }
+    f.write_all(b";\n").unwrap();
+}
+
+/// Write helper for the TLS settings to a https or grpcs upstream, with the directives of
+/// the module passing to it
+fn write_upstream_ssl<T: std::io::Write>(f: &mut BufWriter<T>, ssl: &UpstreamSsl, module: &str) {
+    writeln!(f, "      {}_ssl_server_name on;", module).unwrap();
+    match &ssl.verify_ca {
+        Some(ca) => {
+            writeln!(f, "      {}_ssl_verify on;", module).unwrap();
+            writeln!(
+                f,
+                "      {}_ssl_trusted_certificate {};",
+                module,
+                quote_path(ca)
+            )
+            .unwrap();
+        }
+        None => writeln!(f, "      {}_ssl_verify off;", module).unwrap(),
+    }
+    if let Some((cert, key)) = &ssl.client_cert {
+        writeln!(f, "      {}_ssl_certificate {};", module, quote_path(cert)).unwrap();
+        writeln!(
+            f,
+            "      {}_ssl_certificate_key {};",
+            module,
+            quote_path(key)
+        )
+        .unwrap();
+    }
+}
+
+/// Write helper for the static files section, the root location uses root and the others alias
+fn write_static<T: std::io::Write>(f: &mut BufWriter<T>, location: &str, s: &StaticDir) {
+    let dir = s.dir.trim_end_matches('/');
+    let location = location.trim_matches('/');
+    if location.is_empty() {
+        writeln!(f, "      root {};", quote_path(dir)).unwrap();
+    } else {
+        writeln!(f, "      alias {};", quote_path(&format!("{}/", dir))).unwrap();
+    }
+    if s.autoindex {
+        f.write_all(b"      autoindex on;\n").unwrap();
+    }
+    if let Some(expires) = &s.expires {
+        writeln!(f, "      expires {};", expires).unwrap();
+    }
+    if s.spa {
+        let index = if location.is_empty() {
+            String::from("/index.html")
+        } else {
+            format!("/{}/index.html", location)
+        };
+        writeln!(f, "      try_files $uri $uri/ {};", index).unwrap();
+    }
+}
+
+/// Write helper for the FastCGI section
+fn write_fastcgi<T: std::io::Write>(f: &mut BufWriter<T>, pass: &str, root: &str) {
+    writeln!(f, "      root {};", quote_path(root)).unwrap();
+    f.write_all(b"      include fastcgi_params;\n").unwrap();
+    f.write_all(b"      fastcgi_param SCRIPT_FILENAME $document_root$fastcgi_script_name;\n")
+        .unwrap();
+    f.write_all(b"      fastcgi_index index.php;\n").unwrap();
+    writeln!(f, "      fastcgi_pass {};", pass).unwrap();
+}
+
+/// Write helper for the uwsgi section
+fn write_uwsgi<T: std::io::Write>(f: &mut BufWriter<T>, pass: &str) {
+    f.write_all(b"      include uwsgi_params;\n").unwrap();
+    writeln!(f, "      uwsgi_pass {};", pass).unwrap();
+}
+
+/// Write helper for a location, with an exact location for its path without a trailing slash
+/// when it is served instead of redirected
+fn write_location<T: std::io::Write>(
+    f: &mut BufWriter<T>,
+    name: &str,
+    path: &str,
+    location: &Location,
+) {
+    let bare_path = path.trim_end_matches('/');
+    if location.matching == Match::Prefix && !location.bare_redirect && !bare_path.is_empty() {
+        write_location_block(f, name, path, location, Match::Exact);
+    }
+    write_location_block(f, name, path, location, location.matching);
+}
+
+/// Write helper for a location block according to its target
+fn write_location_block<T: std::io::Write>(
+    f: &mut BufWriter<T>,
+    name: &str,
+    path: &str,
+    location: &Location,
+    matching: Match,
+) {
+    write_match_header(f, path, matching);
+    write_error_variables(f, path, &location.target);
+    let strip_prefix = location.strip_prefix && location.matching != Match::Regex;
+    match &location.target {
+        Target::Proxy(url) => write_proxy(f, url, strip_prefix, matching),
+        Target::Static(s) => write_static(f, path, s),
+        Target::FastCgi { pass, root } => write_fastcgi(f, pass, root),
+        Target::Uwsgi(pass) => write_uwsgi(f, pass),
+        Target::Grpc { url, web } => write_grpc(f, url, *web),
+        Target::Balanced(u) => {
+            let url = format!("{}://{}", u.scheme, upstream_name(name, path));
+            write_proxy(f, &url, strip_prefix, matching)
+        }
+        Target::Stub(stub) => write_stub(f, stub),
+    }
+    if location.streaming && pass_module(&location.target) == Some("proxy") {
+        write_streaming(f);
+    }
+    // the module passing to the target has the TLS and headers directives
+    let module = match location.target {
+        Target::Proxy(_) | Target::Balanced(_) | Target::Grpc { web: true, .. } => Some("proxy"),
+        Target::Grpc { web: false, .. } => Some("grpc"),
+        _ => None,
+    };
+    if let Some(module) = module {
+        if let Some(ssl) = &location.upstream_ssl {
+            write_upstream_ssl(f, ssl, module);
+            if let Target::Balanced(u) = &location.target {
+                writeln!(f, "      proxy_ssl_name {};", u.host()).unwrap();
+            }
+        }
+        for (name, value) in location.request_headers.iter() {
+            writeln!(
+                f,
+                "      {}_set_header {} {};",
+                module,
+                name,
+                quote_value(value)
+            )
+            .unwrap();
+        }
+    }
+    for (name, value) in location.response_headers.iter() {
+        writeln!(
+            f,
+            "      add_header {} {} always;",
+            name,
+            quote_value(value)
+        )
+        .unwrap();
+    }
+    if let Some(cors) = &location.cors {
+        write_cors(f, cors, &location.target);
+    }
+    write_throttle(f, name, path, &location.throttle);
+    write_tuning(f, &location.tuning, &location.target);
+    if let Target::Grpc { web: true, .. } = location.target {
+        // the gRPC-Web clients read the status from the response headers
+        f.write_all(b"      add_header Access-Control-Expose-Headers \"grpc-status, grpc-message, grpc-status-details-bin\" always;\n").unwrap();
+    }
+    f.write_all(b"  }\n").unwrap();
+}
+
+/// Write helper for the stub section, the types are cleared so its content type is always used
+fn write_stub<T: std::io::Write>(f: &mut BufWriter<T>, stub: &Stub) {
+    if let Some(content_type) = &stub.content_type {
+        f.write_all(b"      types { }\n").unwrap();
+        writeln!(f, "      default_type {};", content_type).unwrap();
+    }
+    match &stub.body {
+        StubBody::Empty => writeln!(f, "      return {};", stub.status).unwrap(),
+        // a redirect URL is not quoted like a body
+        StubBody::Inline(url) if (300..400).contains(&stub.status) => {
+            writeln!(f, "      return {} {};", stub.status, quote_path(url)).unwrap()
+        }
+        StubBody::Inline(text) => {
+            writeln!(f, "      return {} {};", stub.status, quote_body(text)).unwrap()
+        }
+        StubBody::File(path) => writeln!(f, "      alias {};", quote_path(path)).unwrap(),
+    }
+}
+
+/// Write helper for the gRPC section, gRPC-Web is proxied over HTTP to a server handling it
+fn write_grpc<T: std::io::Write>(f: &mut BufWriter<T>, url: &str, web: bool) {
+    if web {
+        writeln!(f, "      {}", GRPC_WEB_MARKER).unwrap();
+        writeln!(f, "      proxy_pass {};", url.replacen("grpc", "http", 1)).unwrap();
+        f.write_all(b"      proxy_http_version 1.1;\n").unwrap();
+        f.write_all(b"      proxy_buffering off;\n").unwrap();
+    } else {
+        writeln!(f, "      grpc_pass {};", url).unwrap();
+    }
+}
+
+/// Write helper for the CORS section: the allowed origins are reflected with credentials
+/// and the preflight requests are answered without reaching the target
+fn write_cors<T: std::io::Write>(f: &mut BufWriter<T>, cors: &Cors, target: &Target) {
+    writeln!(f, "      {} {}", CORS_MARKER, cors.origins.join(",")).unwrap();
+    if let Target::Proxy(_) | Target::Balanced(_) | Target::Grpc { web: true, .. } = target {
+        // the proxied app may have its own CORS headers
+        f.write_all(b"      proxy_hide_header Access-Control-Allow-Origin;\n")
+            .unwrap();
+        f.write_all(b"      proxy_hide_header Access-Control-Allow-Credentials;\n")
+            .unwrap();
+    }
+    f.write_all(b"      set $cors_origin \"\";\n").unwrap();
+    writeln!(
+        f,
+        "      if ($http_origin ~* {}) {{",
+        quote_value(&cors.origin_regex())
+    )
+    .unwrap();
+    f.write_all(b"        set $cors_origin $http_origin;\n")
+        .unwrap();
+    f.write_all(b"      }\n").unwrap();
+    f.write_all(b"      if ($request_method = OPTIONS) {\n")
+        .unwrap();
+    write_cors_headers(f, "        ");
+    f.write_all(b"        add_header Access-Control-Allow-Methods \"GET, POST, PUT, PATCH, DELETE, OPTIONS\" always;\n").unwrap();
+    f.write_all(b"        add_header Access-Control-Allow-Headers $http_access_control_request_headers always;\n").unwrap();
+    f.write_all(b"        add_header Access-Control-Max-Age 86400 always;\n")
+        .unwrap();
+    f.write_all(b"        return 204;\n").unwrap();
+    f.write_all(b"      }\n").unwrap();
+    write_cors_headers(f, "      ");
+}
+
+/// Write helper for the CORS headers of all the responses
+fn write_cors_headers<T: std::io::Write>(f: &mut BufWriter<T>, indent: &str) {
+    writeln!(
+        f,
+        "{}add_header Access-Control-Allow-Origin $cors_origin always;",
+        indent
+    )
+    .unwrap();
+    writeln!(
+        f,
+        "{}add_header Access-Control-Allow-Credentials \"true\" always;",
+        indent
+    )
+    .unwrap();
+    writeln!(f, "{}add_header Vary Origin always;", indent).unwrap();
+}
+
+/// The internal location of the error page shown when the backend is down
+const ERROR_PAGE: &str = "/localdev-error.html";
+
+/// Write helper for the variables shown in the error page when the target of a location is down
+fn write_error_variables<T: std::io::Write>(f: &mut BufWriter<T>, path: &str, target: &Target) {
+    if let Target::Static(_) | Target::Stub(_) = target {
+        return;
+    }
+    writeln!(f, "      set $localdev_location {};", quote_value(path)).unwrap();
+    writeln!(
+        f,
+        "      set $localdev_target {};",
+        quote_value(&target.to_string())
+    )
+    .unwrap();
+    if let Some(command) = target.start_command() {
+        writeln!(f, "      set $localdev_start {};", quote_value(&command)).unwrap();
+    }
+}
+
+/// Write helper for the error page of the server, it names the location and target that are down
+/// with the command likely starting it, and reloads until the backend is back.
+/// The imported servers are started with the command of their source instead.
+fn write_error_page<T: std::io::Write>(f: &mut BufWriter<T>, name: &str, source: Option<&str>) {
+    writeln!(f, "  error_page 502 503 504 {};", ERROR_PAGE).unwrap();
+    let start = match source {
+        Some(source) => format!("docker compose -f {} up -d", source),
+        None => String::from("$localdev_start"),
+    };
+    // kept on one line without braces for the location parser
+    let page = format!(
+        "<!DOCTYPE html><html><head><meta charset='utf-8'><meta http-equiv='refresh' content='3'>\
+         <title>{name} is waiting for its backend</title></head>\
+         <body style='font-family: sans-serif; max-width: 40em; margin: 4em auto; line-height: 1.5'>\
+         <h1>🚦 {name}</h1>\
+         <p>localdev is running, but the backend of <b>$localdev_location</b> did not answer \
+         ($status).</p>\
+         <p>It is expected at <code>$localdev_target</code>, start it with:</p>\
+         <pre style='background: #eee; padding: 1em'>{start}</pre>\
+         <p style='color: #888'>This page reloads until the backend is back.</p></body></html>",
+        name = name,
+        start = start,
+    );
+    writeln!(f, "  location = {} {{", ERROR_PAGE).unwrap();
+    f.write_all(b"      internal;\n").unwrap();
+    // the internal redirect runs the server rewrites again, so the defaults are only set here
+    // when the location that is down did not set its own values
+    f.write_all(b"      uninitialized_variable_warn off;\n")
+        .unwrap();
+    for (variable, default) in [
+        ("localdev_location", "$request_uri"),
+        ("localdev_target", "\"its backend\""),
+        ("localdev_start", "\"the app\""),
+    ] {
+        writeln!(
+            f,
+            "      if (${} = \"\") {{ set ${} {}; }}",
+            variable, variable, default
+        )
+        .unwrap();
+    }
+    f.write_all(b"      default_type text/html;\n").unwrap();
+    f.write_all(b"      add_header Cache-Control no-store always;\n")
+        .unwrap();
+    writeln!(f, "      return 200 {};", quote_value(&page)).unwrap();
+    f.write_all(b"  }\n").unwrap();
+}
+
+/// Write helper for the websocket proxy section
+fn write_websocket_proxy<T: std::io::Write>(f: &mut BufWriter<T>, ws: &WebSocket, name: &str) {
+    write_location_header(f, &ws.location, true);
+    f.write_all(b"    proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;\n")
+        .unwrap();
+    f.write_all(b"    proxy_set_header Host $host;\n").unwrap();
+    writeln!(f, "    proxy_pass {};", ws.pass_url(name)).unwrap();
+    f.write_all(b"    proxy_http_version 1.1;\n").unwrap();
+    f.write_all(b"    proxy_set_header Upgrade $http_upgrade;\n")
+        .unwrap();
+    f.write_all(b"    proxy_set_header Connection \"upgrade\";\n")
+        .unwrap();
+    f.write_all(b"  }\n").unwrap();
+}
+
+/// Write helper for the upstream websocket section
+fn write_websocket_upstream<T: std::io::Write>(f: &mut BufWriter<T>, ws: &WebSocket, name: &str) {
+    writeln!(f, "upstream {} {{", ws.upstream_name(name)).unwrap();
+    f.write_all(b"  ip_hash;\n").unwrap();
+    writeln!(f, "  server {};", ws.target).unwrap();
+    f.write_all(b"}\n").unwrap();
+}
+
+/// The position after the last location, for a location added after the others
+pub fn next_position(locations: &HashMap<String, Location>) -> usize {
+    locations
+        .values()
+        .map(|l| l.position + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// The locations in the order they are written, the same ones always give the same file
+fn sorted_locations(locations: &HashMap<String, Location>) -> Vec<(&String, &Location)> {
+    let mut sorted: Vec<(&String, &Location)> = locations.iter().collect();
+    sorted.sort_by(|a, b| (a.1.position, a.0).cmp(&(b.1.position, b.0)));
+    sorted
+}
+
+/// The module passing the requests to the target, which prefixes its directives like proxy_read_timeout
+fn pass_module(target: &Target) -> Option<&'static str> {
+    match target {
+        Target::Proxy(_) | Target::Balanced(_) | Target::Grpc { web: true, .. } => Some("proxy"),
+        Target::Grpc { web: false, .. } => Some("grpc"),
+        Target::FastCgi { .. } => Some("fastcgi"),
+        Target::Uwsgi(_) => Some("uwsgi"),
+        Target::Static(_) | Target::Stub(_) => None,
+    }
+}
+
+/// Write helper for a streaming proxy, the connection to the target is kept open over HTTP/1.1
+/// and the proxies in front of this one are told not to buffer either
+fn write_streaming<T: std::io::Write>(f: &mut BufWriter<T>) {
+    f.write_all(b"      proxy_http_version 1.1;\n").unwrap();
+    f.write_all(b"      proxy_set_header Connection \"\";\n")
+        .unwrap();
+    f.write_all(b"      add_header X-Accel-Buffering no always;\n")
+        .unwrap();
+}
+
+/// Write helper for the body size, timeouts and buffering of a location
+fn write_tuning<T: std::io::Write>(f: &mut BufWriter<T>, tuning: &Tuning, target: &Target) {
+    if let Some(size) = &tuning.max_body_size {
+        writeln!(f, "      client_max_body_size {};", size).unwrap();
+    }
+    let module = match pass_module(target) {
+        Some(module) => module,
+        None => return,
+    };
+    if let Some(timeout) = &tuning.read_timeout {
+        writeln!(f, "      {}_read_timeout {};", module, timeout).unwrap();
+    }
+    if let Some(timeout) = &tuning.send_timeout {
+        writeln!(f, "      {}_send_timeout {};", module, timeout).unwrap();
+    }
+    // gRPC has no buffering and gRPC-Web is already proxied without it
+    let buffered = !matches!(target, Target::Grpc { .. });
+    if let (Some(buffering), true) = (tuning.buffering, buffered) {
+        let value = if buffering { "on" } else { "off" };
+        writeln!(f, "      {}_buffering {};", module, value).unwrap();
+    }
+}
+
+/// The name of the zone of a throttled location, eg: localdev-app.localdev-api-req
+fn zone_name(name: &str, path: &str, kind: &str) -> String {
+    format!("{}-{}", upstream_name(name, path), kind)
+}
+
+/// Write helper for the throttling of a location, the limits answer with 429 Too Many Requests
+fn write_throttle<T: std::io::Write>(f: &mut BufWriter<T>, name: &str, path: &str, t: &Throttle) {
+    if let Some(rate) = &t.rate {
+        writeln!(f, "      limit_rate {};", rate).unwrap();
+    }
+    if let Some((_, burst)) = &t.requests {
+        write!(f, "      limit_req zone={}", zone_name(name, path, "req")).unwrap();
+        if let Some(burst) = burst {
+            write!(f, " burst={}", burst).unwrap();
+        }
+        f.write_all(b";\n      limit_req_status 429;\n").unwrap();
+    }
+    if let Some(connections) = t.connections {
+        let zone = zone_name(name, path, "conn");
+        writeln!(f, "      limit_conn {} {};", zone, connections).unwrap();
+        f.write_all(b"      limit_conn_status 429;\n").unwrap();
+    }
+}
+
+/// Write helper for the zones of a throttled location, shared by all the clients
+fn write_throttle_zones<T: std::io::Write>(
+    f: &mut BufWriter<T>,
+    name: &str,
+    path: &str,
+    t: &Throttle,
+) {
+    if let Some((rate, _)) = &t.requests {
+        let zone = zone_name(name, path, "req");
+        writeln!(
+            f,
+            "limit_req_zone $binary_remote_addr zone={}:1m rate={};",
+            zone, rate
+        )
+        .unwrap();
+    }
+    if t.connections.is_some() {
+        let zone = zone_name(name, path, "conn");
+        writeln!(f, "limit_conn_zone $binary_remote_addr zone={}:1m;", zone).unwrap();
+    }
+}
+
+/// Write helper for the upstream of a location
+fn write_upstream<T: std::io::Write>(f: &mut BufWriter<T>, upstream_name: &str, u: &Upstream) {
+    writeln!(f, "upstream {} {{", upstream_name).unwrap();
+    match u.balance {
+        Balance::RoundRobin => (),
+        Balance::IpHash => f.write_all(b"  ip_hash;\n").unwrap(),
+        Balance::LeastConn => f.write_all(b"  least_conn;\n").unwrap(),
+    }
+    for server in u.servers.iter() {
+        write!(f, "  server {}", server.address).unwrap();
+        if let Some(weight) = server.weight {
+            write!(f, " weight={}", weight).unwrap();
+        }
+        if server.backup {
+            f.write_all(b" backup").unwrap();
+        }
+        f.write_all(b";\n").unwrap();
+    }
+    f.write_all(b"}\n").unwrap();
+}
+
+/// Write helper for the locations of a server
+fn write_locations<T: std::io::Write>(
+    f: &mut BufWriter<T>,
+    name: &str,
+    locations: &HashMap<String, Location>,
+    websockets: &[WebSocket],
+    source: Option<&str>,
+    alt_svc: bool,
+    http2: bool,
+) {
+    write_error_page(f, name, source);
+    if alt_svc {
+        writeln!(f, "  add_header Alt-Svc {} always;", quote_value(ALT_SVC)).unwrap();
+    }
+    for (path, location) in sorted_locations(locations) {
+        // gRPC only goes over HTTP/2
+        if let (false, Target::Grpc { web: false, .. }) = (http2, &location.target) {
+            continue;
+        }
+        // the locations with their own headers do not inherit the server ones
+        if alt_svc {
+            let mut location = location.clone();
+            location.set_header("Alt-Svc", ALT_SVC, true);
+            write_location(f, name, path, &location);
+        } else {
+            write_location(f, name, path, location);
+        }
+    }
+    // add the websocket proxies
+    for ws in websockets {
+        write_websocket_proxy(f, ws, name);
+    }
+}
+
+/// The Alt-Svc header advertising HTTP/3
+const ALT_SVC: &str = "h3=\":443\"; ma=86400";
+
+/// The nginx modules needed by the configuration of a server
+pub fn required_modules(
+    locations: &HashMap<String, Location>,
+    options: &ServerOptions,
+) -> Vec<&'static str> {
+    let mut modules = vec!["http_ssl_module", "http_rewrite_module"];
+    if options.http3 {
+        modules.push("http_v3_module");
+    }
+    if options.basic_auth.is_some() {
+        modules.push("http_auth_basic_module");
+    }
+    for location in locations.values() {
+        if location.throttle.requests.is_some() && !modules.contains(&"http_limit_req_module") {
+            modules.push("http_limit_req_module");
+        }
+        if location.throttle.connections.is_some() && !modules.contains(&"http_limit_conn_module") {
+            modules.push("http_limit_conn_module");
+        }
+        let module = match location.target {
+            Target::Proxy(_) | Target::Balanced(_) => "http_proxy_module",
+            Target::FastCgi { .. } => "http_fastcgi_module",
+            Target::Uwsgi(_) => "http_uwsgi_module",
+            Target::Grpc { web: false, .. } => {
+                // gRPC only goes over HTTP/2
+                if !modules.contains(&"http_v2_module") {
+                    modules.push("http_v2_module");
+                }
+                "http_grpc_module"
+            }
+            Target::Grpc { web: true, .. } => "http_proxy_module",
+            Target::Static(_) | Target::Stub(_) => continue,
+        };
+        if !modules.contains(&module) {
+            modules.push(module);
+        }
+    }
+    modules
+}
+
+/// The name of the log format of a server, each server file has its own
+fn log_format_name(name: &str) -> String {
+    format!("localdev-{}", name)
+}
+
+/// Write helper for the access and error logs of a server
+fn write_logs<T: std::io::Write>(f: &mut BufWriter<T>, name: &str, options: &ServerOptions) {
+    if let Some(dir) = &options.logs {
+        let access_log = logs::access_log_path(Path::new(dir), name);
+        let error_log = logs::error_log_path(Path::new(dir), name);
+        writeln!(
+            f,
+            "  access_log {} {};",
+            quote_path(&access_log.display().to_string()),
+            log_format_name(name)
+        )
+        .unwrap();
+        writeln!(
+            f,
+            "  error_log {};",
+            quote_path(&error_log.display().to_string())
+        )
+        .unwrap();
+    }
+}
+
+/// Write helper for the basic auth of a server
+fn write_basic_auth<T: std::io::Write>(f: &mut BufWriter<T>, options: &ServerOptions) {
+    if let Some(htpasswd) = &options.basic_auth {
+        f.write_all(b"  auth_basic \"localdev\";\n").unwrap();
+        writeln!(f, "  auth_basic_user_file {};", quote_path(htpasswd)).unwrap();
+    }
+}
+
+/// Write the configuration file of a server, with the HTTP version according to its options
+/// and the SSL version with HTTP/2 when nginx has it, using the syntax of its version,
+/// and HTTP/3 if enabled.
+/// The source is recorded as a comment when the server was imported from another file.
+pub fn write_server_config(
+    path: &Path,
+    name: &str,
+    locations: &HashMap<String, Location>,
+    websockets: &[WebSocket],
+    options: &ServerOptions,
+    source: Option<&str>,
+    nginx: &Capabilities,
+) {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .expect("unable to open file");
+    let mut f = BufWriter::new(file);
+
+    if let Some(source) = source {
+        writeln!(f, "{} {}", IMPORT_MARKER, source).unwrap();
+    }
+    if options.logs.is_some() {
+        writeln!(
+            f,
+            "log_format {} escape=json '{}';",
+            log_format_name(name),
+            logs::ACCESS_LOG_FORMAT
+        )
+        .unwrap();
+    }
+
+    // add the HTTP proxy
+    if options.http != HttpMode::HttpsOnly {
+        f.write_all(b"server {\n").unwrap();
+        f.write_all(b"  listen 80;\n").unwrap();
+        f.write_all(b"  listen [::]:80;\n").unwrap();
+        writeln!(f, "  server_name {};", name).unwrap();
+        write_logs(&mut f, name, options);
+        if options.http == HttpMode::Redirect {
+            f.write_all(b"  return 301 https://$host$request_uri;\n")
+                .unwrap();
+        } else {
+            write_basic_auth(&mut f, options);
+            write_locations(&mut f, name, locations, websockets, source, false, false);
+        }
+        f.write_all(b"}\n").unwrap();
+    }
+
+    // write the SSL version
+    let http3 = options.http3 && nginx.has("http_v3_module");
+    f.write_all(b"server {\n").unwrap();
+    if !nginx.has("http_v2_module") || nginx.http2_directive() {
+        f.write_all(b"  listen 443 ssl;\n").unwrap();
+        f.write_all(b"  listen [::]:443 ssl;\n").unwrap();
+        if nginx.has("http_v2_module") {
+            f.write_all(b"  http2 on;\n").unwrap();
+        }
+    } else {
+        f.write_all(b"  listen 443 ssl http2;\n").unwrap();
+        f.write_all(b"  listen [::]:443 ssl http2;\n").unwrap();
+    }
+    if http3 {
+        let reuseport = if options.quic_reuseport {
+            " reuseport"
+        } else {
+            ""
+        };
+        writeln!(f, "  listen 443 quic{};", reuseport).unwrap();
+        writeln!(f, "  listen [::]:443 quic{};", reuseport).unwrap();
+    }
+    writeln!(f, "  server_name {};", name).unwrap();
+    writeln!(f, "  ssl_certificate      {}.pem;", name).unwrap();
+    writeln!(f, "  ssl_certificate_key  {}-key.pem;", name).unwrap();
+    write_logs(&mut f, name, options);
+    f.write_all(b"  ssl_session_cache    shared:SSL:1m;\n")
+        .unwrap();
+    f.write_all(b"  ssl_session_timeout  5m;\n").unwrap();
+    f.write_all(b"  ssl_ciphers  HIGH:!aNULL:!MD5;\n").unwrap();
+    f.write_all(b"  ssl_prefer_server_ciphers  on;\n").unwrap();
+    if let Some(ca) = &options.client_ca {
+        writeln!(f, "  ssl_client_certificate {};", quote_path(ca)).unwrap();
+        f.write_all(b"  ssl_verify_client on;\n").unwrap();
+    }
+    write_basic_auth(&mut f, options);
+    write_locations(&mut f, name, locations, websockets, source, http3, true);
+    f.write_all(b"}\n").unwrap();
+
+    // add the upstream websocket servers
+    for ws in websockets.iter().filter(|ws| ws.upstream) {
+        write_websocket_upstream(&mut f, ws, name);
+    }
+    // and the upstreams of the locations
+    for (path, location) in locations.iter() {
+        if let Target::Balanced(u) = &location.target {
+            write_upstream(&mut f, &upstream_name(name, path), u);
+        }
+        write_throttle_zones(&mut f, name, path, &location.throttle);
+    }
+
+    // done writing
+    f.flush().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write the config of a server then parse it back
+    fn round_trip(
+        name: &str,
+        locations: &HashMap<String, Location>,
+        websockets: &[WebSocket],
+        options: &ServerOptions,
+        source: Option<&str>,
+        nginx: &Capabilities,
+    ) -> ParsedConfig {
+        let path =
+            std::env::temp_dir().join(format!("localdev-{}-{}.conf", name, std::process::id()));
+        write_server_config(&path, name, locations, websockets, options, source, nginx);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        parse_config(&contents, false)
+    }
+
+    /// The locations in the order they are written, without their positions
+    fn ordered(locations: &HashMap<String, Location>) -> Vec<(String, Location)> {
+        sorted_locations(locations)
+            .into_iter()
+            .map(|(path, l)| {
+                let l = Location {
+                    position: 0,
+                    ..l.clone()
+                };
+                (path.to_owned(), l)
+            })
+            .collect()
+    }
+
+    /// Check that the locations are read back the same and in the same order
+    fn assert_round_trip(name: &str, locations: Vec<(&str, Location)>) {
+        let locations: HashMap<String, Location> = locations
+            .into_iter()
+            .enumerate()
+            .map(|(i, (path, l))| (path.to_owned(), Location { position: i, ..l }))
+            .collect();
+        let parsed = round_trip(
+            name,
+            &locations,
+            &[],
+            &ServerOptions::default(),
+            None,
+            &Capabilities::default(),
+        );
+        assert_eq!(parsed.server_names, vec![name, name]);
+        assert_eq!(ordered(&parsed.locations), ordered(&locations));
+    }
+
+    #[test]
+    fn round_trip_targets() {
+        assert_round_trip(
+            "targets.localdev",
+            vec![
+                (
+                    "/",
+                    Location::new(Target::Proxy(String::from("http://localhost:3000/"))),
+                ),
+                (
+                    "/sock",
+                    Location::new(Target::from_url("unix:/run/app.sock:/api/", ".")),
+                ),
+                (
+                    "/assets",
+                    Location::new(Target::Static(StaticDir {
+                        dir: String::from("/srv/app/dist/"),
+                        spa: true,
+                        autoindex: true,
+                        expires: Some(String::from("1h")),
+                    })),
+                ),
+                (
+                    "/php",
+                    Location::new(Target::from_url("fcgi://127.0.0.1:9000", "/srv/php")),
+                ),
+                (
+                    "/fpm",
+                    Location::new(Target::from_url("fcgi+unix:/run/php-fpm.sock", "/srv/php")),
+                ),
+                (
+                    "/py",
+                    Location::new(Target::from_url("uwsgi://127.0.0.1:3031", ".")),
+                ),
+                (
+                    "/pysock",
+                    Location::new(Target::from_url("uwsgi+unix:/run/uwsgi.sock", ".")),
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn round_trip_matching() {
+        let proxy = |url: &str| Location::new(Target::Proxy(url.to_owned()));
+        assert_round_trip(
+            "matching.localdev",
+            vec![
+                (
+                    "/health",
+                    Location {
+                        matching: Match::Exact,
+                        ..proxy("http://localhost:8080/health")
+                    },
+                ),
+                (
+                    "/full",
+                    Location {
+                        strip_prefix: false,
+                        ..proxy("http://localhost:8081")
+                    },
+                ),
+                (
+                    "/bare",
+                    Location {
+                        bare_redirect: false,
+                        ..proxy("http://localhost:8082/")
+                    },
+                ),
+                // the regex locations keep their order
+                (
+                    "^/api/v1/",
+                    Location {
+                        matching: Match::Regex,
+                        strip_prefix: false,
+                        ..proxy("http://localhost:8083")
+                    },
+                ),
+                (
+                    "^/api/",
+                    Location {
+                        matching: Match::Regex,
+                        strip_prefix: false,
+                        ..proxy("http://localhost:8084")
+                    },
+                ),
+                (
+                    "/secure",
+                    Location {
+                        upstream_ssl: Some(UpstreamSsl {
+                            verify_ca: Some(String::from("/etc/ca.pem")),
+                            client_cert: Some((
+                                String::from("/etc/client.pem"),
+                                String::from("/etc/client-key.pem"),
+                            )),
+                        }),
+                        ..proxy("https://localhost:8443/")
+                    },
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn round_trip_headers() {
+        let mut location = Location::new(Target::Proxy(String::from("http://localhost:3000/")));
+        location.set_header("X-Forwarded-Proto", "https", false);
+        location.set_header("X-User", "dev \"admin\"", false);
+        location.set_header("X-Frame-Options", "DENY", true);
+        assert_round_trip("headers.localdev", vec![("/", location)]);
+    }
+
+    #[test]
+    fn round_trip_cors() {
+        let mut location = Location::new(Target::Proxy(String::from("http://localhost:8080/")));
+        location.cors = Some(Cors {
+            origins: vec![
+                String::from("https://app.localdev"),
+                String::from("*.localdev"),
+            ],
+        });
+        location.set_header("X-Frame-Options", "DENY", true);
+        assert_round_trip("cors.localdev", vec![("/api", location)]);
+    }
+
+    #[test]
+    fn round_trip_websockets_and_source() {
+        let mut locations = HashMap::new();
+        locations.insert(
+            String::from("/"),
+            Location::new(Target::Proxy(String::from("http://localhost:3000/"))),
+        );
+        let websocket = |location: &str, target: &str, upstream: bool| WebSocket {
+            location: location.to_owned(),
+            target: target.to_owned(),
+            upstream,
+        };
+        // they are read back sorted by location
+        let websockets = vec![
+            websocket("/hmr", "localhost:5173", false),
+            websocket("/live", "unix:/run/app.sock", true),
+            websocket("/ws", "localhost:3001", true),
+        ];
+        let parsed = round_trip(
+            "ws.localdev",
+            &locations,
+            &websockets,
+            &ServerOptions::default(),
+            Some("/srv/app/docker-compose.yml"),
+            &Capabilities::default(),
+        );
+        assert_eq!(parsed.websockets, websockets);
+        assert_eq!(
+            parsed.source.as_deref(),
+            Some("/srv/app/docker-compose.yml")
+        );
+        assert_eq!(ordered(&parsed.locations), ordered(&locations));
+    }
+
+    #[test]
+    fn round_trip_http_modes() {
+        let mut locations = HashMap::new();
+        locations.insert(
+            String::from("/"),
+            Location::new(Target::Proxy(String::from("http://localhost:3000/"))),
+        );
+        for (http, server_names) in [
+            (HttpMode::Both, 2),
+            (HttpMode::Redirect, 2),
+            (HttpMode::HttpsOnly, 1),
+        ] {
+            let options = ServerOptions {
+                http,
+                ..ServerOptions::default()
+            };
+            let parsed = round_trip(
+                "modes.localdev",
+                &locations,
+                &[],
+                &options,
+                None,
+                &Capabilities::default(),
+            );
+            assert_eq!(parsed.options, options);
+            assert_eq!(parsed.server_names.len(), server_names);
+            assert_eq!(ordered(&parsed.locations), ordered(&locations));
+        }
+    }
+
+    #[test]
+    fn round_trip_http3() {
+        let mut locations = HashMap::new();
+        locations.insert(
+            String::from("/"),
+            Location::new(Target::Proxy(String::from("http://localhost:3000/"))),
+        );
+        let mut api = Location::new(Target::Proxy(String::from("http://localhost:8080/")));
+        api.set_header("X-Frame-Options", "DENY", true);
+        api.position = 1;
+        locations.insert(String::from("/api"), api);
+        let options = ServerOptions {
+            http3: true,
+            quic_reuseport: true,
+            ..ServerOptions::default()
+        };
+        let parsed = round_trip(
+            "quic.localdev",
+            &locations,
+            &[],
+            &options,
+            None,
+            &Capabilities::default(),
+        );
+        assert_eq!(parsed.options, options);
+        // the Alt-Svc header added to every location is not read back as one of theirs
+        assert_eq!(ordered(&parsed.locations), ordered(&locations));
+    }
+
+    #[test]
+    fn round_trip_grpc() {
+        let mut grpc = Location::new(Target::Grpc {
+            url: String::from("grpcs://localhost:50051"),
+            web: false,
+        });
+        grpc.upstream_ssl = Some(UpstreamSsl {
+            verify_ca: Some(String::from("/tmp/ca.pem")),
+            client_cert: None,
+        });
+        grpc.set_header("X-Api-Key", "dev", false);
+        let mut web = Location::new(Target::Grpc {
+            url: String::from("grpc://localhost:8080"),
+            web: true,
+        });
+        web.set_header("X-Grpc-Web", "1", false);
+        // the gRPC location is only in the SSL server, the order is kept anyway
+        assert_round_trip(
+            "grpc.localdev",
+            vec![
+                (
+                    "/",
+                    Location::new(Target::Proxy(String::from("http://localhost:3000/"))),
+                ),
+                ("/package.Service", grpc),
+                ("/api", web),
+            ],
+        );
+    }
+
+    #[test]
+    fn round_trip_balanced() {
+        let server = |address: &str, weight: Option<u32>, backup: bool| UpstreamServer {
+            address: address.to_owned(),
+            weight,
+            backup,
+        };
+        let api = Location::new(Target::Balanced(Upstream {
+            scheme: String::from("http"),
+            servers: vec![
+                server("localhost:8080", Some(3), false),
+                server("localhost:8081", None, false),
+                server("unix:/run/api.sock", None, true),
+            ],
+            balance: Balance::LeastConn,
+        }));
+        let mut secure = Location::new(Target::Balanced(Upstream {
+            scheme: String::from("https"),
+            servers: vec![
+                server("localhost:5001", None, false),
+                server("localhost:5002", None, false),
+            ],
+            balance: Balance::IpHash,
+        }));
+        secure.upstream_ssl = Some(UpstreamSsl {
+            verify_ca: None,
+            client_cert: None,
+        });
+        assert_round_trip(
+            "balanced.localdev",
+            vec![("/api", api), ("/secure", secure)],
+        );
+    }
+
+    #[test]
+    fn address_hosts() {
+        assert_eq!(address_host("localhost:8080"), "localhost");
+        assert_eq!(address_host("[::1]:8080"), "::1");
+        assert_eq!(address_host("app.localdev"), "app.localdev");
+    }
+
+    #[test]
+    fn round_trip_stubs() {
+        let stub = |status: u16, content_type: Option<&str>, body: StubBody| {
+            let mut location = Location::new(Target::Stub(Stub {
+                status,
+                content_type: content_type.map(|t| t.to_owned()),
+                body,
+            }));
+            location.matching = Match::Exact;
+            location
+        };
+        assert_round_trip(
+            "stubs.localdev",
+            vec![
+                (
+                    "/api/user",
+                    stub(
+                        200,
+                        Some("application/json"),
+                        StubBody::Inline(String::from("{\"id\": 1}\n")),
+                    ),
+                ),
+                (
+                    "/api/flags",
+                    stub(
+                        200,
+                        Some("application/json"),
+                        StubBody::File(String::from("/srv/flags.json")),
+                    ),
+                ),
+                ("/gone", stub(410, None, StubBody::Empty)),
+                (
+                    "/old",
+                    stub(301, None, StubBody::Inline(String::from("/new"))),
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn quoted_bodies() {
+        for body in ["ok", "{\"id\": 1}", "a\\b\n\tc\r", "{ } \\\""] {
+            assert_eq!(unquote_body(&quote_body(body)), body);
+        }
+        assert_eq!(quote_body("say \"hi\"\n"), "\"say \\\"hi\\\"\\n\"");
+        assert_eq!(unquote_body("unquoted"), "unquoted");
+    }
+
+    #[test]
+    fn block_depths() {
+        assert_eq!(block_depth("location / {"), 1);
+        assert_eq!(block_depth("}"), -1);
+        assert_eq!(block_depth("if ($a) { set $b c; }"), 0);
+        assert_eq!(block_depth("return 200 \"{\\\"a\\\": {\";"), 0);
+        assert_eq!(block_depth("location = /x { return 200 \"}\";"), 1);
+    }
+
+    #[test]
+    fn round_trip_throttle() {
+        let mut api = Location::new(Target::Proxy(String::from("http://localhost:8080/")));
+        api.throttle = Throttle {
+            rate: Some(String::from("50k")),
+            requests: Some((String::from("30r/m"), Some(10))),
+            connections: Some(1),
+        };
+        let mut slow = Location::new(Target::Proxy(String::from("http://localhost:3000/")));
+        slow.throttle.requests = Some((String::from("2r/s"), None));
+        assert_round_trip("throttle.localdev", vec![("/", slow), ("/api", api)]);
+    }
+
+    #[test]
+    fn round_trip_auth() {
+        let mut locations = HashMap::new();
+        locations.insert(
+            String::from("/"),
+            Location::new(Target::Proxy(String::from("http://localhost:3000/"))),
+        );
+        for http in [HttpMode::Both, HttpMode::HttpsOnly] {
+            let options = ServerOptions {
+                http,
+                basic_auth: Some(String::from(
+                    "/etc/nginx/localdev/auth/auth.localdev.htpasswd",
+                )),
+                client_ca: Some(String::from("/tmp/caroot/rootCA.pem")),
+                ..ServerOptions::default()
+            };
+            let parsed = round_trip(
+                "auth.localdev",
+                &locations,
+                &[],
+                &options,
+                None,
+                &Capabilities::default(),
+            );
+            assert_eq!(parsed.options, options);
+            assert_eq!(ordered(&parsed.locations), ordered(&locations));
+        }
+    }
+
+    #[test]
+    fn round_trip_tuning() {
+        let tuning = Tuning {
+            max_body_size: Some(String::from("100m")),
+            read_timeout: Some(String::from("1h")),
+            send_timeout: Some(String::from("5m")),
+            buffering: Some(false),
+        };
+        let mut upload = Location::new(Target::Proxy(String::from("http://localhost:8080/")));
+        upload.tuning = tuning.clone();
+        let mut php = Location::new(Target::FastCgi {
+            pass: String::from("127.0.0.1:9000"),
+            root: String::from("/srv/app/public"),
+        });
+        php.tuning = Tuning {
+            buffering: Some(true),
+            ..tuning.clone()
+        };
+        let mut grpc = Location::new(Target::Grpc {
+            url: String::from("grpc://localhost:50051"),
+            web: false,
+        });
+        // gRPC has no buffering
+        grpc.tuning = Tuning {
+            buffering: None,
+            ..tuning
+        };
+        assert_round_trip(
+            "tuning.localdev",
+            vec![("/upload", upload), ("/php", php), ("/grpc", grpc)],
+        );
+    }
+
+    #[test]
+    fn round_trip_streaming() {
+        let mut events = Location::new(Target::Proxy(String::from("http://localhost:8080/")));
+        events.streaming = true;
+        events.tuning.buffering = Some(false);
+        events.tuning.read_timeout = Some(String::from("24h"));
+        events.set_header("X-Forwarded-Proto", "https", false);
+        events.set_header("Cache-Control", "no-cache", true);
+        assert_round_trip("sse.localdev", vec![("/events", events)]);
+    }
+
+    #[test]
+    fn round_trip_logs() {
+        let mut locations = HashMap::new();
+        locations.insert(
+            String::from("/"),
+            Location::new(Target::Proxy(String::from("http://localhost:3000/"))),
+        );
+        let options = ServerOptions {
+            logs: Some(String::from("/usr/local/var/log/nginx/localdev")),
+            ..ServerOptions::default()
+        };
+        let parsed = round_trip(
+            "logs.localdev",
+            &locations,
+            &[],
+            &options,
+            None,
+            &Capabilities::default(),
+        );
+        assert_eq!(parsed.options, options);
+        assert_eq!(ordered(&parsed.locations), ordered(&locations));
+    }
+
+    #[test]
+    fn location_of_a_line() {
+        let contents = "server {\n\
+                        \x20 listen 443 ssl;\n\
+                        \x20 location = /health {\n\
+                        \x20     return 200 \"{ ok }\";\n\
+                        \x20 }\n\
+                        \x20 location /api/ {\n\
+                        \x20     if ($request_method = OPTIONS) {\n\
+                        \x20         return 204;\n\
+                        \x20     }\n\
+                        \x20     proxy_pass http://localhost:8080/;\n\
+                        \x20 }\n\
+                        }\n";
+        assert_eq!(location_at(contents, 2), None);
+        assert_eq!(location_at(contents, 4).as_deref(), Some("= /health"));
+        assert_eq!(location_at(contents, 6).as_deref(), Some("/api/"));
+        assert_eq!(location_at(contents, 8).as_deref(), Some("/api/"));
+        assert_eq!(location_at(contents, 10).as_deref(), Some("/api/"));
+        assert_eq!(location_at(contents, 12), None);
+    }
+}
@@ -0,0 +1,181 @@
+use colored::*;
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The nginx log format of the access logs, one JSON object per request
+pub const ACCESS_LOG_FORMAT: &str = r#"{"time": "$time_iso8601", "remote_addr": "$remote_addr", "method": "$request_method", "uri": "$request_uri", "status": $status, "bytes": $body_bytes_sent, "request_time": $request_time, "upstream": "$upstream_addr", "upstream_time": "$upstream_response_time", "referer": "$http_referer", "user_agent": "$http_user_agent"}"#;
+
+/// How often a followed log is checked for new lines
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A request read from an access log
+#[derive(Deserialize, Debug)]
+pub struct Request {
+    pub time: String,
+    pub method: String,
+    pub uri: String,
+    pub status: u16,
+    /// The seconds taken to answer, eg: 0.012
+    pub request_time: f64,
+    /// The address of the target, empty when it was not proxied
+    #[serde(default)]
+    pub upstream: String,
+    /// The seconds taken by the target, with a comma between the tries, eg: 0.010, 0.002
+    #[serde(default)]
+    pub upstream_time: String,
+}
+
+impl Request {
+    /// Read a line of an access log, None if it is not a request
+    pub fn parse(line: &str) -> Option<Request> {
+        serde_json::from_str(line).ok()
+    }
+
+    /// The seconds taken by all the tries of the target, None when it was not proxied
+    pub fn upstream_seconds(&self) -> Option<f64> {
+        let times: Vec<f64> = self
+            .upstream_time
+            .split([',', ':'])
+            .filter_map(|t| t.trim().parse().ok())
+            .collect();
+        Some(times.iter().sum()).filter(|_| !times.is_empty())
+    }
+}
+
+/// The access log of a server
+pub fn access_log_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.access.log", name))
+}
+
+/// The error log of a server
+pub fn error_log_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.error.log", name))
+}
+
+/// Check if a status matches a pattern like 404 or 5xx
+pub fn status_matches(pattern: &str, status: u16) -> bool {
+    let status = status.to_string();
+    pattern.len() == status.len()
+        && pattern
+            .chars()
+            .zip(status.chars())
+            .all(|(p, s)| p == 'x' || p == s)
+}
+
+/// Format a request on one line with its status colored
+pub fn format_request(r: &Request) -> String {
+    // only the time of 2026-10-18T21:06:29+00:00
+    let time = r.time.get(11..19).unwrap_or(&r.time);
+    let status = match r.status {
+        200..=299 => r.status.to_string().green(),
+        300..=399 => r.status.to_string().cyan(),
+        400..=499 => r.status.to_string().yellow(),
+        _ => r.status.to_string().red(),
+    };
+    let mut line = format!(
+        "{} {} {:<6} {} {}ms",
+        time.dimmed(),
+        status,
+        r.method,
+        r.uri,
+        (r.request_time * 1000.0).round()
+    );
+    if !r.upstream.is_empty() && r.upstream != "-" {
+        line.push_str(&format!(" => {}", r.upstream).dimmed().to_string());
+    }
+    line
+}
+
+/// Print the last lines of a log kept by the filter, then the new ones when following it.
+/// A followed log is reopened when it is rotated, either moved away or truncated.
+pub fn tail<F>(path: &Path, lines: usize, follow: bool, filter: F) -> std::io::Result<()>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let file = File::open(path)?;
+    let mut inode = file.metadata()?.ino();
+    let mut reader = BufReader::new(file);
+    let mut kept = vec![];
+    let mut position = 0;
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        position += line.len() as u64;
+        kept.extend(filter(line.trim_end()));
+        line.clear();
+    }
+    for l in kept.iter().skip(kept.len().saturating_sub(lines)) {
+        println!("{}", l);
+    }
+    if !follow {
+        return Ok(());
+    }
+    loop {
+        std::thread::sleep(FOLLOW_INTERVAL);
+        // the last lines written before the rotation are read from the old file
+        while reader.read_line(&mut line)? > 0 {
+            // a line is only complete with its new line
+            if line.ends_with('\n') {
+                position += line.len() as u64;
+                if let Some(l) = filter(line.trim_end()) {
+                    println!("{}", l);
+                }
+                line.clear();
+            }
+        }
+        let rotated = match std::fs::metadata(path) {
+            Ok(m) => m.ino() != inode || m.len() < position,
+            // not created again yet
+            Err(_) => false,
+        };
+        if rotated {
+            let file = File::open(path)?;
+            inode = file.metadata()?.ino();
+            reader = BufReader::new(file);
+            position = 0;
+            line.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_patterns() {
+        assert!(status_matches("404", 404));
+        assert!(status_matches("5xx", 502));
+        assert!(status_matches("xxx", 200));
+        assert!(!status_matches("5xx", 404));
+        assert!(!status_matches("40", 404));
+        assert!(!status_matches("4xxx", 404));
+    }
+
+    #[test]
+    fn parse_request() {
+        let line = ACCESS_LOG_FORMAT
+            .replace("$time_iso8601", "2026-10-18T21:06:29+00:00")
+            .replace("$remote_addr", "127.0.0.1")
+            .replace("$request_method", "GET")
+            .replace("$request_uri", "/api/users?page=2")
+            .replace("$status", "502")
+            .replace("$body_bytes_sent", "157")
+            .replace("$request_time", "0.004")
+            .replace("$upstream_addr", "127.0.0.1:8080")
+            .replace("$upstream_response_time", "0.003")
+            .replace("$http_referer", "")
+            .replace("$http_user_agent", "curl/8.5.0");
+        let request = Request::parse(&line).unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.uri, "/api/users?page=2");
+        assert_eq!(request.status, 502);
+        assert_eq!(request.upstream, "127.0.0.1:8080");
+        assert!(Request::parse("2026/10/18 21:06:29 [error] 12#12: connect() failed").is_none());
+    }
+}
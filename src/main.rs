@@ -1,9 +1,32 @@
 use clap::{IntoApp, Parser};
 use clap_complete::{generate, Generator};
 use colored::*;
+use config::{
+    Balance, Location, Match, ServerOptions, StaticDir, Stub, StubBody, Target, Upstream,
+    UpstreamServer, UpstreamSsl, WebSocket,
+};
+use nginx::Capabilities;
 use regex::Regex;
-use std::{collections::HashMap, io::BufWriter, io::Write, path::Path};
+use std::{
+    collections::HashMap,
+    io::Write,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+};
 mod cli;
+mod compose;
+mod config;
+mod logs;
+mod nginx;
+mod stats;
+mod stream;
+
+/// The default (/) proxy target of a new server
+const DEFAULT_TARGET: &str = "http://localhost:3000";
+/// The websocket proxy of a new server
+const DEFAULT_WS: &str = "/ws:localhost:3000";
+/// The read timeout of the streaming proxies, which can wait long for the next event
+const SSE_READ_TIMEOUT: &str = "24h";
 
 fn mkcert(nginx_dir_path: &Path, name: &str, verbose: bool) {
     if verbose {
@@ -26,20 +49,171 @@ fn mkcert(nginx_dir_path: &Path, name: &str, verbose: bool) {
     assert!(output.status.success());
 }
 
+/// Get the path of the mkcert CA certificate
+fn mkcert_ca() -> Option<String> {
+    let output = std::process::Command::new("mkcert")
+        .arg("-CAROOT")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let ca_root = String::from_utf8(output.stdout).ok()?;
+    Some(format!("{}/rootCA.pem", ca_root.trim()))
+}
+
+/// Mint a client certificate signed by the mkcert CA in the current directory,
+/// the error explains why it could not be done
+fn mkcert_client(name: &str, pkcs12: bool, verbose: bool) -> Result<(), String> {
+    let mut command = std::process::Command::new("mkcert");
+    command.arg("-client");
+    if pkcs12 {
+        command.arg("-pkcs12");
+    }
+    let output = match command.arg(name).output() {
+        Ok(output) => output,
+        Err(e) => return Err(format!("{}, is mkcert installed?", e)),
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if verbose {
+        println!("mkcert status ? {}", output.status);
+        println!("{}", stderr);
+    }
+    if !output.status.success() {
+        return Err(stderr.trim().to_owned());
+    }
+    Ok(())
+}
+
+/// The htpasswd file of the basic auth of a server, managed by localdev
+fn htpasswd_path(nginx_dir_path: &Path, name: &str) -> PathBuf {
+    nginx_dir_path
+        .join("localdev")
+        .join("auth")
+        .join(format!("{}.htpasswd", name))
+}
+
+/// Hash a password for a htpasswd file with openssl, using the Apache variant of MD5
+fn hash_password(password: &str) -> Option<String> {
+    let mut child = std::process::Command::new("openssl")
+        .args(["passwd", "-apr1", "-stdin"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .ok()?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(password.as_bytes())
+        .ok()?;
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_owned())
+}
+
+/// Add the users given as user:password to a htpasswd file, replacing the ones with the same name,
+/// or all of them when the file is replaced
+fn write_htpasswd(path: &Path, users: &[String], replace: bool) -> bool {
+    let current = if replace {
+        String::new()
+    } else {
+        std::fs::read_to_string(path).unwrap_or_default()
+    };
+    let mut lines: Vec<String> = current.lines().map(|l| l.to_owned()).collect();
+    for user in users {
+        let (name, password) = match user.split_once(':') {
+            Some((n, p)) if !n.is_empty() && !p.is_empty() => (n, p),
+            _ => {
+                println!(
+                    "❗ Invalid basic auth user, expected user:password: {}",
+                    user
+                );
+                return false;
+            }
+        };
+        let hash = match hash_password(password) {
+            Some(hash) => hash,
+            None => {
+                println!("❗ Could not hash the password, is openssl installed?");
+                return false;
+            }
+        };
+        lines.retain(|l| l.split(':').next() != Some(name));
+        lines.push(format!("{}:{}", name, hash));
+    }
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, format!("{}\n", lines.join("\n"))).unwrap();
+    true
+}
+
+/// The directory of the access and error logs of the servers, managed by localdev
+fn log_dir(nginx_dir_path: &Path) -> String {
+    let dir = nginx_dir_path.join("localdev").join("logs");
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.display().to_string()
+}
+
+/// Remove the htpasswd file of a server if it is one managed by localdev
+fn remove_htpasswd(nginx_dir_path: &Path, name: &str, options: &ServerOptions) {
+    let path = htpasswd_path(nginx_dir_path, name);
+    if options.basic_auth.as_deref() == Some(&path.display().to_string()) && path.exists() {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
 fn reload_nginx(verbose: bool) {
     if verbose {
         println!("Running nginx reload ...");
     }
-    let output = std::process::Command::new("nginx")
+    let output = match std::process::Command::new("nginx")
         .arg("-s")
         .arg("reload")
         .output()
-        .unwrap();
+    {
+        Ok(output) => output,
+        Err(e) => {
+            println!("❗ Failed to reload nginx: {}", e);
+            println!("❗  nginx was not found, install it or add it to PATH");
+            std::process::exit(1);
+        }
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
     if verbose {
         println!("nginx reload status ? {}", output.status);
         println!("{}", String::from_utf8(output.stdout).unwrap());
+        println!("{}", stderr);
+    }
+    if output.status.success() {
+        return;
+    }
+    println!("❗ Failed to reload nginx");
+    let diagnostics = nginx::diagnose(&stderr);
+    if diagnostics.is_empty() {
+        for l in stderr.lines() {
+            println!("❗  {}", l);
+        }
+    }
+    for diagnostic in diagnostics {
+        println!("❗ {}", diagnostic.message.red());
+        if let Some((file, line)) = &diagnostic.file {
+            // point at the line and its location in the config
+            let contents = std::fs::read_to_string(file).unwrap_or_default();
+            match config::location_at(&contents, *line) {
+                Some(location) => println!("❗  in {}:{}, location {}", file, line, location),
+                None => println!("❗  in {}:{}", file, line),
+            }
+            if let Some(l) = line.checked_sub(1).and_then(|i| contents.lines().nth(i)) {
+                println!("❗  {}", l.trim().dimmed());
+            }
+        }
+        if let Some(fix) = &diagnostic.fix {
+            println!("❗  {}", fix);
+        }
     }
-    assert!(output.status.success(), "Failed to reload nginx");
+    std::process::exit(1);
 }
 
 fn open_server(server_name: &str) {
@@ -51,26 +225,72 @@ fn open_server(server_name: &str) {
 
 fn print_server(
     server_name: &str,
-    server_name_to_proxies: &HashMap<String, HashMap<String, String>>,
+    server_name_to_proxies: &HashMap<String, HashMap<String, Location>>,
 ) {
     println!();
     let s = format!("https://{}", server_name);
     println!(" 🚦 {}", s.bold());
-    // sort the proxies by location
+    // sort the proxies by location, regex locations are shown after a ~
     let proxies_map = server_name_to_proxies.get(server_name).unwrap();
-    let mut proxies: Vec<&String> = proxies_map.keys().collect();
-    proxies.sort();
+    let mut proxies: Vec<(String, &Location)> = proxies_map
+        .iter()
+        .map(|(location, target)| match target.matching {
+            Match::Regex => (format!("~{}", location.trim()), target),
+            _ => (format!("/{}", location.trim().trim_matches('/')), target),
+        })
+        .collect();
+    proxies.sort_by(|a, b| a.0.cmp(&b.0));
     // align the output according to the longest location
-    let l = proxies.iter().map(|x| x.len()).max().unwrap();
-    for location in proxies {
-        let target = proxies_map.get(location).unwrap().trim().trim_matches('/');
-        let location = location.trim().trim_matches('/');
+    let l = proxies.iter().map(|(x, _)| x.len()).max().unwrap();
+    for (location, target) in proxies {
+        // static files are shown with a folder
+        let icon = match target.target {
+            _ if target.streaming => "📡",
+            Target::Static(_) => "📁",
+            Target::Stub(_) => "🧪",
+            _ => "🚀",
+        };
         // padd the location to the longest location l
-        let location = format!("/{:<l$}", location, l = l);
-        println!("     🚀 {}=> {}", location.green(), target.blue());
+        let location = format!("{:<l$}", location, l = l + 1);
+        let target = target.to_string();
+        println!("     {} {}=> {}", icon, location.green(), target.blue());
     }
 }
 
+/// Print the TCP and UDP proxies
+fn print_streams(streams: &[stream::Stream]) {
+    let mut streams: Vec<&stream::Stream> = streams.iter().collect();
+    streams.sort_by(|a, b| (&a.name, a.port).cmp(&(&b.name, b.port)));
+    for s in streams {
+        println!();
+        let scheme = match s.mode {
+            stream::StreamMode::Udp => "udp",
+            _ => "tcp",
+        };
+        let address = format!("{}://{}:{}", scheme, s.name, s.port);
+        let note = match s.mode {
+            stream::StreamMode::Tls => " (tls)",
+            stream::StreamMode::Sni => " (tls passthrough)",
+            _ => "",
+        };
+        println!(" 🔌 {} => {}{}", address.bold(), s.target.blue(), note);
+    }
+}
+
+/// Write the config file of the streams on a port, it is removed when there are none left
+fn write_streams(stream_dir: &Path, nginx_dir_path: &Path, streams: &[stream::Stream], port: u16) {
+    let path = stream::config_path(stream_dir, port);
+    let on_port: Vec<&stream::Stream> = streams.iter().filter(|s| s.port == port).collect();
+    if on_port.is_empty() {
+        std::fs::remove_file(path).unwrap();
+        return;
+    }
+    // the unix sockets of the TLS streams
+    let socket_dir = nginx_dir_path.join("localdev").join("sockets");
+    std::fs::create_dir_all(&socket_dir).unwrap();
+    stream::write_port_config(&path, port, &on_port, &socket_dir);
+}
+
 /// Find server_name or with added extension .localdev in the parsed config.
 fn find_server_name(server_name: &str, server_names: &[String]) -> Option<String> {
     let mut name_local: String = server_name.to_owned();
@@ -89,55 +309,95 @@ fn print_completer<G: Generator>(generator: G) {
     generate(generator, &mut app, name, &mut std::io::stdout());
 }
 
-/// Write helper for the proxy location header
-fn write_location_header<T: std::io::Write>(
-    f: &mut BufWriter<T>,
-    location: &str,
-    is_websocket: bool,
-) {
-    f.write_all(b"  location ").unwrap();
-    if !location.starts_with('/') {
-        f.write_all(b"/").unwrap();
+/// Expand a proxy target given as a port, eg: :8080 or 8080/api to http://localhost:8080/api
+fn proxy_target(target: &str, with_protocol: bool) -> String {
+    let port_target_rx = Regex::new(r"^:?([0-9]+.*)$").unwrap();
+    let target = target.trim();
+    // a URL with only a port is on localhost, eg: https://:5001
+    if let Some((scheme, port)) = target.split_once("://:") {
+        return format!("{}://localhost:{}", scheme, port);
     }
-    write!(f, "{}", location).unwrap();
-    if !is_websocket && !location.ends_with('/') {
-        f.write_all(b"/").unwrap();
+    // if the target matches port_target_rx
+    match port_target_rx.captures(target) {
+        Some(caps) => {
+            let port = caps.get(1).unwrap().as_str();
+            if with_protocol {
+                format!("http://localhost:{}", port)
+            } else {
+                format!("localhost:{}", port)
+            }
+        }
+        None => target.to_owned(),
     }
-    f.write_all(b" {\n").unwrap();
 }
 
-/// Write helper for the proxy section
-fn write_proxy<T: std::io::Write>(f: &mut BufWriter<T>, location: &str, target: &str) {
-    write_location_header(f, location, false);
-    write!(f, "      proxy_pass {}", target).unwrap();
-    if !target.ends_with('/') {
-        f.write_all(b"/").unwrap();
+/// Parse the target of a location, several servers separated by commas make an upstream,
+/// eg: :8080*3,:8081,:8082+backup with the weight and backup servers
+fn parse_target(target: &str, root: &str) -> Option<Target> {
+    if !target.contains(',') {
+        return Some(Target::from_url(&proxy_target(target, true), root));
     }
-    f.write_all(b";\n").unwrap();
-    f.write_all(b"  }\n").unwrap();
-}
-
-/// Write helper for the websocket proxy section
-fn write_websocket_proxy<T: std::io::Write>(f: &mut BufWriter<T>, location: &str, name: &str) {
-    write_location_header(f, location, true);
-    f.write_all(b"    proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;\n")
-        .unwrap();
-    f.write_all(b"    proxy_set_header Host $host;\n").unwrap();
-    writeln!(f, "    proxy_pass http://ws-backend-{};", name).unwrap();
-    f.write_all(b"    proxy_http_version 1.1;\n").unwrap();
-    f.write_all(b"    proxy_set_header Upgrade $http_upgrade;\n")
-        .unwrap();
-    f.write_all(b"    proxy_set_header Connection \"upgrade\";\n")
-        .unwrap();
-    f.write_all(b"  }\n").unwrap();
-}
-
-/// Write helper for the upstream websocket section
-fn write_websocket_upstream<T: std::io::Write>(f: &mut BufWriter<T>, upstream: &str, name: &str) {
-    writeln!(f, "upstream ws-backend-{} {{", name).unwrap();
-    f.write_all(b"  ip_hash;\n").unwrap();
-    writeln!(f, "  server {};", upstream).unwrap();
-    f.write_all(b"}\n").unwrap();
+    let mut scheme: Option<String> = None;
+    let mut servers = vec![];
+    for part in target.split(',') {
+        let (part, backup) = match part.trim().strip_suffix("+backup") {
+            Some(p) => (p, true),
+            None => (part.trim(), false),
+        };
+        let (part, weight) = match part.rsplit_once('*') {
+            Some((p, w)) => match w.parse::<u32>() {
+                Ok(w) => (p, Some(w)),
+                Err(_) => {
+                    println!("❗ Invalid weight: {}", w);
+                    return None;
+                }
+            },
+            None => (part, None),
+        };
+        let url = proxy_target(part, true);
+        let (s, address) = match url.split_once("://") {
+            Some((s @ ("http" | "https"), address)) => (s, address.trim_end_matches('/')),
+            _ if url.starts_with("unix:") => ("http", url.as_str()),
+            _ => {
+                println!("❗ Only HTTP servers can be balanced: {}", part);
+                return None;
+            }
+        };
+        if address.contains('/') && !address.starts_with("unix:") {
+            println!("❗ The balanced servers can not have a path: {}", part);
+            return None;
+        }
+        if scheme.is_some_and(|scheme| scheme != s) {
+            println!(
+                "❗ The balanced servers must all use http or https: {}",
+                target
+            );
+            return None;
+        }
+        scheme = Some(s.to_owned());
+        servers.push(UpstreamServer {
+            address: address.to_owned(),
+            weight,
+            backup,
+        });
+    }
+    // the https servers are verified with the name of a single host
+    if scheme.as_deref() == Some("https")
+        && servers
+            .iter()
+            .any(|s| config::address_host(&s.address) != config::address_host(&servers[0].address))
+    {
+        println!(
+            "❗ The balanced https servers must all have the same host: {}",
+            target
+        );
+        return None;
+    }
+    Some(Target::Balanced(Upstream {
+        scheme: scheme.unwrap_or_else(|| String::from("http")),
+        servers,
+        balance: Balance::RoundRobin,
+    }))
 }
 
 fn parse_proxy_arg(arg: &str, with_protocol: bool) -> Option<(String, String)> {
@@ -148,21 +408,11 @@ fn parse_proxy_arg(arg: &str, with_protocol: bool) -> Option<(String, String)> {
         split = arg.splitn(2, ':').collect::<Vec<&str>>();
     }
     if split.len() == 2 {
-        let port_target_rx = Regex::new(r"^:?([0-9]+.*)$").unwrap();
         let mut location = split[0].trim().to_string();
         if !location.starts_with('/') {
             location = format!("/{}", location);
         }
-        let mut target = split[1].trim().to_string();
-        // if the target matches port_target_rx
-        if let Some(caps) = port_target_rx.captures(&target) {
-            let port = caps.get(1).unwrap().as_str();
-            if with_protocol {
-                target = format!("http://localhost:{}", port);
-            } else {
-                target = format!("localhost:{}", port);
-            }
-        }
+        let target = proxy_target(split[1], with_protocol);
 
         Some((location, target))
     } else {
@@ -171,6 +421,254 @@ fn parse_proxy_arg(arg: &str, with_protocol: bool) -> Option<(String, String)> {
     }
 }
 
+/// Parse a static location argument like /=./dist, the directory must exist
+fn parse_static_arg(arg: &str, spa: bool) -> Option<(String, Target)> {
+    let split = match arg.split_once('=') {
+        Some(s) => Some(s),
+        None => arg.split_once(':'),
+    };
+    match split {
+        Some((location, dir)) => {
+            let mut location = location.trim().to_string();
+            if !location.starts_with('/') {
+                location = format!("/{}", location);
+            }
+            // nginx needs an absolute path
+            match std::fs::canonicalize(dir.trim()) {
+                Ok(dir) => Some((
+                    location,
+                    Target::Static(StaticDir {
+                        dir: dir.display().to_string(),
+                        spa,
+                        ..Default::default()
+                    }),
+                )),
+                Err(e) => {
+                    println!("❗ Invalid static directory: {}: {}", dir, e);
+                    None
+                }
+            }
+        }
+        None => {
+            println!("❗ Invalid static location: {}", arg);
+            None
+        }
+    }
+}
+
+/// Parse a stub location argument like api/flags=200:./flags.json, the body is a file when it is
+/// a path, else it is inline and can start with its content type, eg: 200:text/csv:a,b
+fn parse_stub_arg(arg: &str) -> Option<(String, Target)> {
+    let parsed = arg.split_once('=').and_then(|(location, response)| {
+        let (status, body) = response.split_once(':').unwrap_or((response, ""));
+        let status: u16 = status.trim().parse().ok()?;
+        Some((location, status, body)).filter(|_| (200..600).contains(&status))
+    });
+    let (location, status, body) = match parsed {
+        Some(parsed) => parsed,
+        None => {
+            println!("❗ Invalid stub: {}", arg);
+            return None;
+        }
+    };
+    let mut location = location.trim().to_string();
+    if !location.starts_with('/') {
+        location = format!("/{}", location);
+    }
+    let content_type_rx = Regex::new(r"^[a-z]+/[a-z0-9.+-]+$").unwrap();
+    let (content_type, body) = match body.split_once(':') {
+        Some((t, b)) if content_type_rx.is_match(t) => (Some(t.to_owned()), b),
+        _ => (None, body),
+    };
+    let body = if body.is_empty() {
+        StubBody::Empty
+    } else if (300..400).contains(&status) {
+        StubBody::Inline(body.to_owned())
+    } else if body.starts_with("./") || body.starts_with("../") || body.starts_with('/') {
+        if status != 200 {
+            println!("❗ A stub file can only be returned with 200: {}", arg);
+            return None;
+        }
+        match std::fs::canonicalize(body) {
+            Ok(path) => StubBody::File(path.display().to_string()),
+            Err(e) => {
+                println!("❗ Invalid stub file: {}: {}", body, e);
+                return None;
+            }
+        }
+    } else {
+        StubBody::Inline(body.to_owned())
+    };
+    // the content type is guessed from the body
+    let content_type = match &body {
+        StubBody::Empty => None,
+        StubBody::Inline(_) if (300..400).contains(&status) => None,
+        StubBody::Inline(text) => content_type.or_else(|| {
+            Some(String::from(match text.trim_start().chars().next() {
+                Some('{' | '[') => "application/json",
+                Some('<') => "text/html",
+                _ => "text/plain",
+            }))
+        }),
+        StubBody::File(path) => content_type.or_else(|| {
+            let extension = Path::new(path).extension().and_then(|e| e.to_str());
+            Some(String::from(match extension.unwrap_or_default() {
+                "json" => "application/json",
+                "html" | "htm" => "text/html",
+                "xml" => "application/xml",
+                "js" => "application/javascript",
+                "css" => "text/css",
+                "txt" => "text/plain",
+                "svg" => "image/svg+xml",
+                "png" => "image/png",
+                "jpg" | "jpeg" => "image/jpeg",
+                _ => "application/octet-stream",
+            }))
+        }),
+    };
+    Some((
+        location,
+        Target::Stub(Stub {
+            status,
+            content_type,
+            body,
+        }),
+    ))
+}
+
+/// Make a server name from a directory name, keeping only the characters valid in a hostname
+fn dir_server_name(dir: &str) -> String {
+    let name = Path::new(dir)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    name.trim_matches('-').to_owned()
+}
+
+/// Check that the unix socket exists, the backend creates it when it starts
+fn is_socket(path: &str) -> bool {
+    std::fs::metadata(path)
+        .map(|m| m.file_type().is_socket())
+        .unwrap_or(false)
+}
+
+/// Find the location of the given path, with or without the slashes
+fn find_location<'a>(
+    locations: &'a mut HashMap<String, Location>,
+    path: &str,
+) -> Option<&'a mut Location> {
+    let path = path.trim().trim_matches('/');
+    locations
+        .iter_mut()
+        .find(|(p, _)| p.trim_matches('/') == path)
+        .map(|(_, l)| l)
+}
+
+/// Set the target of a location, keeping its other options if it already exists,
+/// else it is added after the others
+fn set_location_target(locations: &mut HashMap<String, Location>, path: &str, target: Target) {
+    match find_location(locations, path) {
+        Some(l) => {
+            // a stub only matches its exact path, unlike the target replacing it
+            if matches!(l.target, Target::Stub(_)) && !matches!(target, Target::Stub(_)) {
+                l.matching = Match::Prefix;
+            }
+            l.target = target;
+        }
+        None => {
+            let location = Location {
+                position: config::next_position(locations),
+                ..Location::new(target)
+            };
+            locations.insert(path.to_owned(), location);
+        }
+    }
+}
+
+/// Parse a header argument like X-Forwarded-Proto: https or for a location api=X-User: dev
+fn parse_header_arg(arg: &str) -> Option<(Option<String>, String, String)> {
+    let (name, value) = arg.split_once(':')?;
+    let (location, name) = match name.split_once('=') {
+        Some((l, n)) => (Some(l.trim().to_owned()), n),
+        None => (None, name),
+    };
+    let name = name.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((location, name.to_owned(), value.trim().to_owned()))
+}
+
+/// Parse a CORS argument like app,http://localhost:5173 or for a location api=*.localdev.
+/// The names without a scheme are local servers and the hosts with a port are on HTTP.
+fn parse_cors_arg(arg: &str) -> (Option<String>, Vec<String>) {
+    let (location, origins) = match arg.split_once('=') {
+        Some((l, o)) => (Some(l.trim().to_owned()), o),
+        None => (None, arg),
+    };
+    let origins = origins
+        .split(',')
+        .map(|o| o.trim())
+        .filter(|o| !o.is_empty())
+        .map(|o| match o {
+            "*" => String::from("*"),
+            "localdev" | "*.localdev" => String::from("*.localdev"),
+            o if o.contains("://") => o.trim_end_matches('/').to_owned(),
+            o if o.contains(':') => format!("http://{}", o),
+            o => format!("https://{}", localdev_name(o)),
+        })
+        .collect();
+    (location, origins)
+}
+
+/// Apply an argument like api=50k to its location or else to all of them.
+/// The setter checks the value, false when it is invalid or the location does not exist.
+fn set_location_arg<F>(
+    locations: &mut HashMap<String, Location>,
+    option: &str,
+    arg: &str,
+    set: F,
+) -> bool
+where
+    F: Fn(&mut Location, &str) -> bool,
+{
+    // the location is before the first = unless it is in the value, like a burst
+    let (path, value) = match arg.split_once('=') {
+        Some((path, value)) if !path.contains(',') => (Some(path.trim()), value.trim()),
+        _ => (None, arg.trim()),
+    };
+    let locations: Vec<&mut Location> = match path {
+        Some(path) => match find_location(locations, path) {
+            Some(l) => vec![l],
+            None => {
+                println!("❗ No location {} for {}", path, option);
+                return false;
+            }
+        },
+        None => locations.values_mut().collect(),
+    };
+    for l in locations {
+        if !set(l, value) {
+            println!("❗ Invalid {}: {}", option, arg);
+            return false;
+        }
+    }
+    true
+}
+
+/// Add the .localdev domain to the given name if it has none
+fn localdev_name(server_name: &str) -> String {
+    let mut name = server_name.to_owned();
+    if !name.ends_with(".localdev") {
+        name.push_str(".localdev");
+    }
+    name
+}
+
 fn main() {
     let args = cli::Args::parse();
 
@@ -201,15 +699,33 @@ fn main() {
     let include_rx = Regex::new(r"include\s+([a-zA-Z0-9]+)/\*").unwrap();
     // read the file
     let contents = std::fs::read_to_string(nginx_path).unwrap();
+    // the directories included in the stream block are for the TCP and UDP proxies
+    let mut stream_dirs = vec![];
+    let mut depth = 0;
+    let mut stream_depth = None;
     // get the lines that match the pattern "include" and are not comments
     for line in contents.lines() {
-        if line.trim_start().starts_with("include") {
+        let line = line.trim_start();
+        if line.starts_with("stream") && line.contains('{') && stream_depth.is_none() {
+            stream_depth = Some(depth);
+        }
+        if line.starts_with("include") {
             // check if it is a recursive include of a directory: matches regex include_rx
             let caps = include_rx.captures(line);
             if let Some(c) = caps {
                 let dir = c.get(1).unwrap().as_str();
                 // add to found_dirs
-                found_dirs.push(dir);
+                if stream_depth.is_some() {
+                    stream_dirs.push(dir);
+                } else {
+                    found_dirs.push(dir);
+                }
+            }
+        }
+        if !line.starts_with('#') {
+            depth += line.matches('{').count() as i32 - line.matches('}').count() as i32;
+            if stream_depth.is_some_and(|d| depth <= d) {
+                stream_depth = None;
             }
         }
     }
@@ -231,9 +747,25 @@ fn main() {
 
     // join nginx_path and found_dir
     let found_dir = nginx_dir_path.join(found_dirs[0]);
+    let stream_dir = match stream_dirs.as_slice() {
+        [dir] => Some(nginx_dir_path.join(dir)),
+        _ => None,
+    };
 
-    let server_name_rx = Regex::new(r"server_name\s+(.*)\s*;").unwrap();
-    let location_rx = Regex::new(r"location\s+(.*)\s*\{").unwrap();
+    // read the TCP and UDP proxies
+    let mut streams = vec![];
+    if let Some(dir) = &stream_dir {
+        if args.verbose > 0 {
+            println!("Found stream directory: {}", dir.display());
+        }
+        for p in dir.read_dir().unwrap() {
+            let child = p.unwrap().path();
+            if child.is_file() {
+                let contents = std::fs::read_to_string(&child).unwrap();
+                streams.extend(stream::parse_config(&contents));
+            }
+        }
+    }
 
     // store list of server names
     let mut server_names = vec![];
@@ -241,6 +773,12 @@ fn main() {
     let mut server_name_to_path = HashMap::new();
     // save a Map of server_name to a list of proxies
     let mut server_name_to_proxies = HashMap::new();
+    // save a Map of server_name to the file it was imported from
+    let mut server_name_to_source = HashMap::new();
+    // save a Map of server_name to its websocket proxies
+    let mut server_name_to_websockets: HashMap<String, Vec<WebSocket>> = HashMap::new();
+    // save a Map of server_name to its options
+    let mut server_name_to_options = HashMap::new();
 
     // check all the files in found_dir
     for p in found_dir.read_dir().unwrap() {
@@ -250,65 +788,27 @@ fn main() {
             if args.verbose > 0 {
                 println!("Processing FILE: {}", child.display());
             }
-            let mut proxies = HashMap::new();
             // read the file
             let contents = std::fs::read_to_string(child.clone()).unwrap();
-            // get the lines starting with "server_name"
-            let mut server_name: Option<&str> = None;
-            let mut location: Option<&str> = None;
-            for line in contents.lines() {
-                let line = line.trim_start();
-                if line.starts_with("server_name") {
-                    let caps = server_name_rx.captures(line);
-                    if let Some(c) = caps {
-                        server_name = Some(c.get(1).unwrap().as_str());
-                        // add to found_dirs
-                        server_names.push(server_name.unwrap().to_owned());
-                        server_name_to_path.insert(server_name.unwrap().to_owned(), child.clone());
-                    }
-                }
-                if line.starts_with("location") {
-                    let caps = location_rx.captures(line);
-                    if let Some(c) = caps {
-                        location = Some(c.get(1).unwrap().as_str());
-                    }
-                }
-                if line.starts_with("proxy_pass") {
-                    // add to proxies for the current location
-                    match location {
-                        Some(l) => {
-                            // get the target
-                            let target = line
-                                .split_whitespace()
-                                .nth(1)
-                                .unwrap()
-                                .trim()
-                                .trim_end_matches(';')
-                                .trim();
-                            proxies.insert(l.to_owned(), target.to_owned());
-                        }
-                        None => {
-                            if args.verbose > 0 {
-                                println!("No current location for proxy_pass: {}", line);
-                            }
-                        }
-                    }
+            let parsed = config::parse_config(&contents, args.verbose > 0);
+            for name in parsed.server_names {
+                server_name_to_path.insert(name.to_owned(), child.clone());
+                let mut proxies = parsed.locations.clone();
+                for ws in parsed.websockets.iter() {
+                    proxies.insert(ws.location.to_owned(), ws.listing());
                 }
-                if line.starts_with('}') && location.is_some() {
-                    location = None;
-                }
-            }
-            // if server_name str is initialized
-            if let Some(name) = server_name {
+                server_name_to_websockets.insert(name.to_owned(), parsed.websockets.clone());
                 server_name_to_proxies.insert(name.to_owned(), proxies);
+                server_name_to_options.insert(name.to_owned(), parsed.options.clone());
+                if let Some(s) = &parsed.source {
+                    server_name_to_source.insert(name.to_owned(), s.to_owned());
+                }
+                server_names.push(name);
             }
         }
     }
     // only care about the server names ending with .localdev domains
-    server_names = server_names
-        .into_iter()
-        .filter(|s| s.ends_with(".localdev"))
-        .collect();
+    server_names.retain(|s| s.ends_with(".localdev"));
     // remove duplicates from server_names
     server_names.sort();
     server_names.dedup();
@@ -317,6 +817,157 @@ fn main() {
             print_completer(shell);
             return;
         }
+        Some(cli::Commands::Doctor {}) => {
+            let capabilities = Capabilities::detect();
+            println!();
+            match capabilities.version {
+                Some((major, minor, patch)) => {
+                    println!(" 🩺 nginx {}.{}.{}", major, minor, patch)
+                }
+                None => println!(" 🩺 nginx version unknown"),
+            }
+            if !capabilities.is_known() {
+                println!("❗ Could not run nginx -V to find its modules");
+                return;
+            }
+            for feature in nginx::FEATURES.iter() {
+                if capabilities.has(feature.module) {
+                    println!("     ✅ {} ({})", feature.name, feature.module);
+                } else {
+                    println!("     ❌ {} needs {}", feature.name, feature.module.red());
+                }
+            }
+            if !streams.is_empty() {
+                let missing: Vec<&str> = stream::required_modules(&streams)
+                    .into_iter()
+                    .filter(|m| !capabilities.has(m))
+                    .collect();
+                if !missing.is_empty() {
+                    println!(
+                        "❗ The streams need the missing modules: {}",
+                        missing.join(", ")
+                    );
+                }
+            }
+            // the modules missing for the current servers
+            for name in server_names.iter() {
+                let missing: Vec<&str> = config::required_modules(
+                    &server_name_to_proxies[name],
+                    &server_name_to_options[name],
+                )
+                .into_iter()
+                .filter(|m| !capabilities.has(m))
+                .collect();
+                if !missing.is_empty() {
+                    println!(
+                        "❗ {} needs the missing modules: {}",
+                        name,
+                        missing.join(", ")
+                    );
+                }
+            }
+            return;
+        }
+        Some(cli::Commands::Logs {
+            server_name,
+            follow,
+            status,
+            path,
+            lines,
+            errors,
+        }) => {
+            let found = match find_server_name(&server_name, server_names.as_slice()) {
+                Some(f) => f,
+                None => {
+                    println!("Server name not found: {}", server_name);
+                    return;
+                }
+            };
+            let dir = match &server_name_to_options[&found].logs {
+                Some(dir) => PathBuf::from(dir),
+                None => {
+                    println!("❗ This server has no logs: {}", found);
+                    println!("❗  update it with localdev add {} -u", server_name);
+                    return;
+                }
+            };
+            if status
+                .as_deref()
+                .is_some_and(|s| !Regex::new(r"^[1-5][0-9x]{2}$").unwrap().is_match(s))
+            {
+                println!("❗ Invalid status, eg: 404 or 5xx: {}", status.unwrap());
+                return;
+            }
+            let path = path.map(|p| format!("/{}", p.trim_start_matches('/')));
+            let result = if errors {
+                let log = logs::error_log_path(&dir, &found);
+                logs::tail(&log, lines, follow, |l| Some(l.to_owned()))
+            } else {
+                let log = logs::access_log_path(&dir, &found);
+                logs::tail(&log, lines, follow, |l| {
+                    let request = logs::Request::parse(l)?;
+                    let kept = status
+                        .as_deref()
+                        .is_none_or(|s| logs::status_matches(s, request.status))
+                        && path.as_deref().is_none_or(|p| request.uri.starts_with(p));
+                    Some(logs::format_request(&request)).filter(|_| kept)
+                })
+            };
+            if let Err(e) = result {
+                println!("❗ Could not read the logs of {}: {}", found, e);
+            }
+            return;
+        }
+        Some(cli::Commands::Stats {
+            server_name,
+            slowest,
+            json,
+        }) => {
+            let found = match find_server_name(&server_name, server_names.as_slice()) {
+                Some(f) => f,
+                None => {
+                    println!("Server name not found: {}", server_name);
+                    return;
+                }
+            };
+            let dir = match &server_name_to_options[&found].logs {
+                Some(dir) => PathBuf::from(dir),
+                None => {
+                    println!("❗ This server has no logs: {}", found);
+                    println!("❗  update it with localdev add {} -u", server_name);
+                    return;
+                }
+            };
+            let log = logs::access_log_path(&dir, &found);
+            let requests: Vec<logs::Request> = match std::fs::read_to_string(&log) {
+                Ok(contents) => contents.lines().filter_map(logs::Request::parse).collect(),
+                Err(e) => {
+                    println!("❗ Could not read the logs of {}: {}", found, e);
+                    return;
+                }
+            };
+            let stats = stats::compute(&found, &requests, &server_name_to_proxies[&found], slowest);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+            } else {
+                stats::print(&stats);
+            }
+            return;
+        }
+        Some(cli::Commands::ClientCert { name, pkcs12 }) => {
+            if let Err(e) = mkcert_client(&name, pkcs12, args.verbose > 0) {
+                println!("❗ Could not mint the client certificate: {}", e);
+                return;
+            }
+            println!();
+            println!(" 🔑 {} signed by the mkcert CA", name.bold());
+            println!("     {}-client.pem", name);
+            println!("     {}-client-key.pem", name);
+            if pkcs12 {
+                println!("     {}-client.p12 (the password is changeit)", name);
+            }
+            return;
+        }
         Some(cli::Commands::Reload {}) => {
             reload_nginx(args.verbose > 0);
             return;
@@ -341,7 +992,7 @@ fn main() {
             match found {
                 Some(f) => {
                     // filter the server_names, those are printed below as the default command
-                    server_names = server_names.into_iter().filter(|n| n == &f).collect();
+                    server_names.retain(|n| n == &f);
                     // if open is set, open the serer in the browser
                     if open {
                         open_server(&f);
@@ -362,6 +1013,32 @@ fn main() {
                     let path_to_file = server_name_to_path[&f].to_owned();
                     //remove the file
                     std::fs::remove_file(path_to_file).unwrap();
+                    // and its credentials
+                    remove_htpasswd(nginx_dir_path, &f, &server_name_to_options[&f]);
+                    // another HTTP/3 server gets the reuseport of the QUIC listener
+                    if server_name_to_options[&f].quic_reuseport {
+                        let other = server_names
+                            .iter()
+                            .find(|n| **n != f && server_name_to_options[*n].http3);
+                        if let Some(other) = other {
+                            let mut options = server_name_to_options[other].clone();
+                            options.quic_reuseport = true;
+                            let websockets = &server_name_to_websockets[other];
+                            let mut locations = server_name_to_proxies[other].clone();
+                            for ws in websockets {
+                                locations.remove(&ws.location);
+                            }
+                            config::write_server_config(
+                                &server_name_to_path[other],
+                                other,
+                                &locations,
+                                websockets,
+                                &options,
+                                server_name_to_source.get(other).map(|s| s.as_str()),
+                                &Capabilities::detect(),
+                            );
+                        }
+                    }
                     reload_nginx(args.verbose > 0);
                 }
                 None => {
@@ -374,16 +1051,47 @@ fn main() {
             server_name,
             default_target,
             ws,
+            ws_plain,
+            no_ws,
             proxy,
+            sse,
+            balance,
+            static_dir,
+            stub,
+            spa,
+            root,
+            header,
+            response_header,
+            cors,
+            limit_rate,
+            limit_req,
+            limit_conn,
+            max_body_size,
+            read_timeout,
+            send_timeout,
+            buffering,
+            basic_auth,
+            client_ca,
+            http,
+            http3,
+            upstream_verify,
+            upstream_cert,
+            upstream_key,
+            preserve_prefix,
+            exact,
+            no_redirect,
+            grpc_web,
+            regex,
             force,
+            update,
             open,
         }) => {
             let found = find_server_name(&server_name, server_names.as_slice());
-            match found {
+            match &found {
                 Some(f) => {
-                    if !force {
+                    if !force && !update {
                         println!("❗ This server already exists: {}", f);
-                        println!("❗  use --force to reconfigure");
+                        println!("❗  use --force to reconfigure or --update to change it");
                         return;
                     }
                 }
@@ -391,39 +1099,469 @@ fn main() {
                     println!("Server name not found: {}", server_name);
                 }
             }
+            // an update starts from the current configuration
+            let existing = if update { found.as_ref() } else { None };
+            let mut websockets = match existing {
+                Some(f) => server_name_to_websockets[f].clone(),
+                None => vec![],
+            };
+            let mut proxies: HashMap<String, Location> = match existing {
+                Some(f) => server_name_to_proxies[f].clone(),
+                None => HashMap::new(),
+            };
+            for ws in websockets.iter() {
+                proxies.remove(&ws.location);
+            }
+
+            let mut options: ServerOptions = match existing {
+                Some(f) => server_name_to_options[f].clone(),
+                None => ServerOptions::default(),
+            };
+            if let Some(http) = http {
+                options.http = http;
+            }
+            options.logs = Some(log_dir(nginx_dir_path));
+            let capabilities = Capabilities::detect();
+            if http3 {
+                if !capabilities.has("http_v3_module") {
+                    println!("❗ HTTP/3 needs nginx built with the http_v3_module");
+                    println!("❗  use localdev doctor to check the nginx modules");
+                    return;
+                }
+                options.http3 = true;
+            }
 
-            // parse the websocket param
-            let mut websocket: Option<(String, String)> = None;
-            if !ws.is_empty() {
-                websocket = parse_proxy_arg(&ws, false);
+            // parse the websocket params, they replace the ones on the same location,
+            // --no-ws removes the current ones
+            if no_ws {
+                websockets.clear();
             }
-            let (ws_l, ws_t) = match websocket {
-                Some((w, l)) => (Some(w), Some(l)),
-                None => (None, None),
+            let ws = if existing.is_none() && !no_ws && ws.is_empty() && ws_plain.is_empty() {
+                vec![String::from(DEFAULT_WS)]
+            } else {
+                ws
             };
+            for (w, upstream) in ws
+                .iter()
+                .map(|w| (w, true))
+                .chain(ws_plain.iter().map(|w| (w, false)))
+            {
+                if let Some((location, target)) = parse_proxy_arg(w, false) {
+                    websockets.retain(|ws| ws.location != location);
+                    websockets.push(WebSocket {
+                        location,
+                        target,
+                        upstream,
+                    });
+                }
+            }
 
             // if there is no domain auto add the .devlocal to server_name
-            let mut name = server_name;
-            if !name.ends_with(".localdev") {
-                name.push_str(".localdev");
-            }
+            let name = localdev_name(&server_name);
+            // reuseport is only allowed on one of the QUIC listeners
+            options.quic_reuseport = options.http3
+                && !server_name_to_options
+                    .iter()
+                    .any(|(n, o)| *n != name && o.quic_reuseport);
             if args.verbose > 0 {
                 println!("No current configuration for server: {}", name);
             }
-            // generate the SSL ssl_certificates using mkcert
-            mkcert(nginx_dir_path, &name, args.verbose > 0);
-
-            let mut proxies = HashMap::new();
-            proxies.insert(String::from("/"), default_target);
+            // the document root of the FastCGI targets
+            let root = match std::fs::canonicalize(&root) {
+                Ok(r) => r.display().to_string(),
+                Err(e) => {
+                    println!("❗ Invalid document root: {}: {}", root, e);
+                    return;
+                }
+            };
+            let default_target = match existing {
+                Some(_) => default_target,
+                None => default_target.or_else(|| Some(String::from(DEFAULT_TARGET))),
+            };
+            if let Some(default_target) = default_target {
+                match parse_target(&default_target, &root) {
+                    Some(target) => set_location_target(&mut proxies, "/", target),
+                    None => return,
+                }
+            }
 
             // test proxy arg
             if !proxy.is_empty() {
                 for p in proxy.iter() {
                     let res = parse_proxy_arg(p, true);
                     if let Some((location, target)) = res {
-                        proxies.insert(location, target);
+                        match parse_target(&target, &root) {
+                            Some(target) => set_location_target(&mut proxies, &location, target),
+                            None => return,
+                        }
+                    }
+                }
+            }
+            // the streaming proxies are never buffered and wait long for the events
+            for s in sse.iter() {
+                let location = if s.contains('=') || s.contains(':') {
+                    let (location, target) = match parse_proxy_arg(s, true) {
+                        Some(parsed) => parsed,
+                        None => return,
+                    };
+                    match parse_target(&target, &root) {
+                        Some(target) => set_location_target(&mut proxies, &location, target),
+                        None => return,
+                    }
+                    location
+                } else {
+                    s.to_owned()
+                };
+                let l = match find_location(&mut proxies, &location) {
+                    Some(l) => l,
+                    None => {
+                        println!("❗ No location {} for --sse", location);
+                        return;
+                    }
+                };
+                if !matches!(l.target, Target::Proxy(_) | Target::Balanced(_)) {
+                    println!("❗ --sse needs a HTTP proxy target for {}", location);
+                    return;
+                }
+                l.streaming = true;
+                l.tuning.buffering = Some(false);
+                l.tuning.read_timeout = Some(String::from(SSE_READ_TIMEOUT));
+            }
+            // static locations replace the proxies on the same location
+            for s in static_dir.iter() {
+                if let Some((location, target)) = parse_static_arg(s, spa) {
+                    set_location_target(&mut proxies, &location, target);
+                }
+            }
+            // the stubs only match their exact path, they can not replace another target
+            for s in stub.iter() {
+                match parse_stub_arg(s) {
+                    Some((location, target)) => {
+                        if let Some(l) = find_location(&mut proxies, &location) {
+                            if !matches!(l.target, Target::Stub(_)) {
+                                println!("❗ The location already has a target: {}", s);
+                                println!("❗  a stub can only replace another stub");
+                                return;
+                            }
+                        }
+                        set_location_target(&mut proxies, &location, target);
+                        find_location(&mut proxies, &location).unwrap().matching = Match::Exact;
+                    }
+                    None => return,
+                }
+            }
+            // regex locations always give the full path to their target
+            for r in regex.iter() {
+                match r.split_once('=') {
+                    Some((location, target)) => {
+                        let target = match parse_target(target, &root) {
+                            Some(target) => target,
+                            None => return,
+                        };
+                        // nginx tries the regex locations in the order they are given
+                        set_location_target(&mut proxies, location.trim(), target);
+                        let l = find_location(&mut proxies, location).unwrap();
+                        l.matching = Match::Regex;
+                        l.strip_prefix = false;
+                    }
+                    None => println!("❗ Invalid regex proxy: {}", r),
+                }
+            }
+            // how the locations match and pass their path
+            let mut set_option = |option: &str, paths: &[String], set: fn(&mut Location)| {
+                for path in paths {
+                    match find_location(&mut proxies, path) {
+                        Some(l) => set(l),
+                        None => println!("❗ No location {} for {}", path, option),
+                    }
+                }
+            };
+            set_option("--preserve-prefix", &preserve_prefix, |l| {
+                l.strip_prefix = false
+            });
+            set_option("--exact", &exact, |l| l.matching = Match::Exact);
+            set_option("--no-redirect", &no_redirect, |l| l.bare_redirect = false);
+            set_option("--grpc-web", &grpc_web, |l| match &mut l.target {
+                Target::Grpc { web, .. } => {
+                    *web = true;
+                    // the browsers call it from the other local servers by default
+                    if l.cors.is_none() {
+                        l.cors = Some(config::Cors {
+                            origins: vec![String::from("*.localdev")],
+                        });
+                    }
+                }
+                _ => println!("❗ --grpc-web needs a grpc:// or grpcs:// target"),
+            });
+            // how the upstreams choose their server
+            for b in balance.iter() {
+                let (path, strategy) = b.split_once('=').unwrap_or(("/", b));
+                let strategy = match clap::ArgEnum::from_str(strategy.trim(), true) {
+                    Ok(strategy) => strategy,
+                    Err(_) => {
+                        println!("❗ Invalid balance: {}", b);
+                        return;
+                    }
+                };
+                match find_location(&mut proxies, path).map(|l| &mut l.target) {
+                    Some(Target::Balanced(u)) => u.balance = strategy,
+                    Some(_) => println!("❗ --balance needs several servers for {}", path),
+                    None => println!("❗ No location {} for --balance", path),
+                }
+            }
+            for (path, location) in proxies.iter() {
+                if let Target::Balanced(u) = &location.target {
+                    if u.balance == Balance::IpHash && u.servers.iter().any(|s| s.backup) {
+                        println!(
+                            "❗ The backup servers can not be used with ip-hash for {}",
+                            path
+                        );
+                        return;
+                    }
+                }
+            }
+
+            // the headers are added to the given location or else to all of them
+            for (h, response) in header
+                .iter()
+                .map(|h| (h, false))
+                .chain(response_header.iter().map(|h| (h, true)))
+            {
+                let (location, name, value) = match parse_header_arg(h) {
+                    Some(parsed) => parsed,
+                    None => {
+                        println!("❗ Invalid header: {}", h);
+                        continue;
+                    }
+                };
+                match location {
+                    Some(path) => match find_location(&mut proxies, &path) {
+                        Some(l) => l.set_header(&name, &value, response),
+                        None => println!("❗ No location {} for the header {}", path, name),
+                    },
+                    None => {
+                        for l in proxies.values_mut() {
+                            l.set_header(&name, &value, response);
+                        }
+                    }
+                }
+            }
+
+            // the CORS origins are allowed on the given location or else on all of them
+            for c in cors.iter() {
+                let (location, origins) = parse_cors_arg(c);
+                let cors = config::Cors { origins };
+                match location {
+                    Some(path) => match find_location(&mut proxies, &path) {
+                        Some(l) => l.cors = Some(cors),
+                        None => println!("❗ No location {} for the CORS origins", path),
+                    },
+                    None => {
+                        for l in proxies.values_mut() {
+                            l.cors = Some(cors.clone());
+                        }
+                    }
+                }
+            }
+
+            // the throttling reproduces a slow network, off removes it
+            let rate_rx = Regex::new(r"^[0-9]+[kKmM]?$").unwrap();
+            let requests_rx = Regex::new(r"^([0-9]+r/[sm])(,burst=([0-9]+))?$").unwrap();
+            for arg in limit_rate.iter() {
+                let valid = set_location_arg(&mut proxies, "--limit-rate", arg, |l, v| match v {
+                    "off" => {
+                        l.throttle.rate = None;
+                        true
+                    }
+                    v if rate_rx.is_match(v) => {
+                        l.throttle.rate = Some(v.to_owned());
+                        true
+                    }
+                    _ => false,
+                });
+                if !valid {
+                    return;
+                }
+            }
+            for arg in limit_req.iter() {
+                let valid = set_location_arg(&mut proxies, "--limit-req", arg, |l, v| {
+                    match (v, requests_rx.captures(v)) {
+                        ("off", _) => l.throttle.requests = None,
+                        (_, Some(caps)) => {
+                            let burst = caps.get(3).and_then(|b| b.as_str().parse().ok());
+                            l.throttle.requests = Some((caps[1].to_owned(), burst));
+                        }
+                        (_, None) => return false,
+                    }
+                    true
+                });
+                if !valid {
+                    return;
+                }
+            }
+            for arg in limit_conn.iter() {
+                let valid = set_location_arg(&mut proxies, "--limit-conn", arg, |l, v| {
+                    match (v, v.parse::<u32>().ok().filter(|c| *c > 0)) {
+                        ("off", _) => l.throttle.connections = None,
+                        (_, Some(connections)) => l.throttle.connections = Some(connections),
+                        (_, None) => return false,
+                    }
+                    true
+                });
+                if !valid {
+                    return;
+                }
+            }
+
+            // the tuning of the body size, timeouts and buffering
+            let size_rx = Regex::new(r"^[0-9]+[kKmMgG]?$").unwrap();
+            let time_rx = Regex::new(r"^[0-9]+(ms|s|m|h|d)?$").unwrap();
+            for arg in max_body_size.iter() {
+                let valid = set_location_arg(&mut proxies, "--max-body-size", arg, |l, v| {
+                    l.tuning.max_body_size = Some(v.to_owned());
+                    size_rx.is_match(v)
+                });
+                if !valid {
+                    return;
+                }
+            }
+            for arg in read_timeout.iter() {
+                let valid = set_location_arg(&mut proxies, "--read-timeout", arg, |l, v| {
+                    l.tuning.read_timeout = Some(v.to_owned());
+                    time_rx.is_match(v)
+                });
+                if !valid {
+                    return;
+                }
+            }
+            for arg in send_timeout.iter() {
+                let valid = set_location_arg(&mut proxies, "--send-timeout", arg, |l, v| {
+                    l.tuning.send_timeout = Some(v.to_owned());
+                    time_rx.is_match(v)
+                });
+                if !valid {
+                    return;
+                }
+            }
+            for arg in buffering.iter() {
+                let valid = set_location_arg(&mut proxies, "--buffering", arg, |l, v| {
+                    l.tuning.buffering = match v {
+                        "on" => None,
+                        "off" => Some(false),
+                        _ => return false,
+                    };
+                    true
+                });
+                if !valid {
+                    return;
+                }
+            }
+
+            let missing: Vec<&str> = config::required_modules(&proxies, &options)
+                .into_iter()
+                .filter(|m| !capabilities.has(m))
+                .collect();
+            if !missing.is_empty() {
+                println!("❗ nginx is missing the modules: {}", missing.join(", "));
+                println!("❗  use localdev doctor to check the nginx modules");
+            }
+
+            // the https targets verify the upstream certificate against a CA
+            let upstream_verify_given = upstream_verify.is_some();
+            let verify_ca = match upstream_verify.as_deref().unwrap_or("off") {
+                "off" => None,
+                "mkcert" => match mkcert_ca() {
+                    Some(ca) => Some(ca),
+                    None => {
+                        println!("❗ Could not find the mkcert CA, is mkcert installed?");
+                        return;
+                    }
+                },
+                ca => match std::fs::canonicalize(ca) {
+                    Ok(ca) => Some(ca.display().to_string()),
+                    Err(e) => {
+                        println!("❗ Invalid CA file: {}: {}", ca, e);
+                        return;
+                    }
+                },
+            };
+            let mut client_cert = None;
+            if let Some((cert, key)) = upstream_cert.zip(upstream_key) {
+                match (std::fs::canonicalize(&cert), std::fs::canonicalize(&key)) {
+                    (Ok(cert), Ok(key)) => {
+                        client_cert = Some((cert.display().to_string(), key.display().to_string()))
+                    }
+                    _ => {
+                        println!("❗ Invalid client certificate: {} {}", cert, key);
+                        return;
+                    }
+                }
+            }
+            let upstream_ssl = UpstreamSsl {
+                verify_ca,
+                client_cert,
+            };
+            // the current settings are kept unless new ones are given
+            let replace_ssl = upstream_verify_given || upstream_ssl.client_cert.is_some();
+            for location in proxies.values_mut() {
+                if location.target.is_tls() && (replace_ssl || location.upstream_ssl.is_none()) {
+                    location.upstream_ssl = Some(upstream_ssl.clone());
+                }
+            }
+
+            // the basic auth users are kept in a htpasswd file, off removes them
+            if basic_auth.iter().any(|u| u == "off") {
+                remove_htpasswd(nginx_dir_path, &name, &options);
+                options.basic_auth = None;
+            } else if !basic_auth.is_empty() {
+                let htpasswd = htpasswd_path(nginx_dir_path, &name);
+                // a forced config starts without the users of the one it replaces
+                if !write_htpasswd(&htpasswd, &basic_auth, existing.is_none()) {
+                    return;
+                }
+                options.basic_auth = Some(htpasswd.display().to_string());
+            }
+            // the client certificates are verified against a CA
+            match client_ca.as_deref() {
+                None => (),
+                Some("off") => options.client_ca = None,
+                Some("mkcert") => match mkcert_ca() {
+                    Some(ca) => options.client_ca = Some(ca),
+                    None => {
+                        println!("❗ Could not find the mkcert CA, is mkcert installed?");
+                        return;
                     }
+                },
+                Some(ca) => match std::fs::canonicalize(ca) {
+                    Ok(ca) => options.client_ca = Some(ca.display().to_string()),
+                    Err(e) => {
+                        println!("❗ Invalid CA file: {}: {}", ca, e);
+                        return;
+                    }
+                },
+            }
+            // plain HTTP would not ask for the client certificate
+            if options.client_ca.is_some()
+                && http.is_none()
+                && options.http == config::HttpMode::Both
+            {
+                println!("HTTP is redirected to HTTPS to require the client certificates");
+                options.http = config::HttpMode::Redirect;
+            }
+
+            // the unix sockets must exist
+            let mut sockets: Vec<&str> =
+                proxies.values().filter_map(|l| l.target.socket()).collect();
+            for ws in websockets.iter() {
+                sockets.extend(ws.target.strip_prefix("unix:"));
+            }
+            let missing: Vec<&&str> = sockets.iter().filter(|s| !is_socket(s)).collect();
+            if !missing.is_empty() {
+                for s in missing {
+                    println!("❗ Unix socket not found: {}", s);
                 }
+                println!("❗  start the backends before adding them");
+                return;
             }
 
             if args.verbose > 0 {
@@ -432,65 +1570,38 @@ fn main() {
                     println!("Target: {}", target);
                 }
             }
+            // generate the SSL ssl_certificates using mkcert
+            mkcert(nginx_dir_path, &name, args.verbose > 0);
 
-            // add it
+            // add it, an update keeps the current file
             server_names.push(name.to_owned());
             let mut file_name = name.to_owned();
             file_name.push_str(".conf");
-            let new_path = found_dir.join(file_name);
-
-            // write to new_path
-            {
-                let file = std::fs::OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .truncate(true)
-                    .open(new_path.clone())
-                    .expect("unable to open file");
-                let mut f = BufWriter::new(file);
-
-                // add the HTTP proxy
-                f.write_all(b"server {\n").unwrap();
-                f.write_all(b"  listen 80;\n").unwrap();
-                f.write_all(b"  listen [::]:80;\n").unwrap();
-                writeln!(f, "  server_name {};", name).unwrap();
-                for (location, target) in proxies.iter() {
-                    write_proxy(&mut f, location, target);
-                }
-                // add a websocket proxy
-                if ws_l.is_some() && ws_t.is_some() {
-                    write_websocket_proxy(&mut f, ws_l.as_deref().unwrap(), &name);
-                }
-                f.write_all(b"}\n").unwrap();
-
-                // write the SSL version
-                f.write_all(b"server {\n").unwrap();
-                f.write_all(b"  listen 443 ssl;\n").unwrap();
-                writeln!(f, "  server_name {};", name).unwrap();
-                writeln!(f, "  ssl_certificate      {}.pem;", name).unwrap();
-                writeln!(f, "  ssl_certificate_key  {}-key.pem;", name).unwrap();
-                f.write_all(b"  ssl_session_cache    shared:SSL:1m;\n")
-                    .unwrap();
-                f.write_all(b"  ssl_session_timeout  5m;\n").unwrap();
-                f.write_all(b"  ssl_ciphers  HIGH:!aNULL:!MD5;\n").unwrap();
-                f.write_all(b"  ssl_prefer_server_ciphers  on;\n").unwrap();
-                for (location, target) in proxies.iter() {
-                    write_proxy(&mut f, location, target);
-                }
-                // add a websocket proxy
-                if ws_l.is_some() && ws_t.is_some() {
-                    write_websocket_proxy(&mut f, ws_l.as_deref().unwrap(), &name);
-                }
-                f.write_all(b"}\n").unwrap();
-
-                // add the upstream websocket server
-                if let Some(ws_l) = ws_l {
-                    write_websocket_upstream(&mut f, ws_t.as_deref().unwrap(), &name);
-                    proxies.insert(ws_l, format!("ws-backend-{}", &name));
+            let new_path = match existing {
+                Some(f) => server_name_to_path[f].to_owned(),
+                None => found_dir.join(file_name),
+            };
+            let source = existing.and_then(|f| server_name_to_source.get(f));
+            // the users of a forced config without basic auth are removed
+            if let (None, Some(f)) = (existing, &found) {
+                if options.basic_auth.is_none() {
+                    remove_htpasswd(nginx_dir_path, &name, &server_name_to_options[f]);
                 }
+            }
 
-                // done writing
-                f.flush().unwrap();
+            // write to new_path
+            config::write_server_config(
+                &new_path,
+                &name,
+                &proxies,
+                &websockets,
+                &options,
+                source.map(|s| s.as_str()),
+                &capabilities,
+            );
+            // add the websocket proxies to the listed proxies
+            for ws in websockets.iter() {
+                proxies.insert(ws.location.to_owned(), ws.listing());
             }
 
             server_name_to_path.insert(name.to_owned(), new_path);
@@ -508,6 +1619,246 @@ fn main() {
             }
             return;
         }
+        Some(cli::Commands::ServeDir {
+            path,
+            name,
+            spa,
+            autoindex,
+            cache,
+            force,
+            no_open,
+        }) => {
+            let (location, mut target) = match parse_static_arg(&format!("/={}", path), spa) {
+                Some(s) => s,
+                None => return,
+            };
+            if let Target::Static(s) = &mut target {
+                s.autoindex = autoindex;
+                s.expires = cache;
+            }
+            // name the server after the directory
+            let name = match name {
+                Some(n) => localdev_name(&n),
+                None => match target {
+                    Target::Static(ref s) if !dir_server_name(&s.dir).is_empty() => {
+                        localdev_name(&dir_server_name(&s.dir))
+                    }
+                    _ => {
+                        println!("❗ Could not name the server for: {}", path);
+                        println!("❗  use --name to set it");
+                        return;
+                    }
+                },
+            };
+            if let Some(f) = find_server_name(&name, server_names.as_slice()) {
+                if !force {
+                    println!("❗ This server already exists: {}", f);
+                    println!("❗  use --force to reconfigure");
+                    return;
+                }
+            }
+            mkcert(nginx_dir_path, &name, args.verbose > 0);
+
+            let mut locations = HashMap::new();
+            locations.insert(location, Location::new(target));
+            let new_path = found_dir.join(format!("{}.conf", name));
+            config::write_server_config(
+                &new_path,
+                &name,
+                &locations,
+                &[],
+                &ServerOptions {
+                    logs: Some(log_dir(nginx_dir_path)),
+                    ..Default::default()
+                },
+                None,
+                &Capabilities::detect(),
+            );
+            if args.verbose > 0 {
+                println!(">> Wrote new configuration for server: {}", name);
+            }
+            reload_nginx(args.verbose > 0);
+            server_name_to_proxies.insert(name.to_owned(), locations);
+            print_server(&name, &server_name_to_proxies);
+            if !no_open {
+                open_server(&name);
+            }
+            return;
+        }
+        Some(cli::Commands::Import {
+            source:
+                cli::ImportSource::Compose {
+                    file,
+                    server,
+                    force,
+                },
+        }) => {
+            // use the given file or look for the default ones
+            let compose_path = match file {
+                Some(f) => PathBuf::from(f),
+                None => match compose::DEFAULT_FILES
+                    .iter()
+                    .map(PathBuf::from)
+                    .find(|p| p.is_file())
+                {
+                    Some(p) => p,
+                    None => {
+                        println!("❗ Could not find a compose file in the current directory");
+                        return;
+                    }
+                },
+            };
+            let compose_path = match std::fs::canonicalize(&compose_path) {
+                Ok(p) => p,
+                Err(e) => {
+                    println!("❗ Could not find {}: {}", compose_path.display(), e);
+                    return;
+                }
+            };
+            let servers = match compose::load(&compose_path, server.as_deref()) {
+                Ok(s) => s,
+                Err(e) => {
+                    println!("❗ {}", e);
+                    return;
+                }
+            };
+            let source = compose_path.display().to_string();
+            if args.verbose > 0 {
+                println!("Found {} servers in {}", servers.len(), source);
+            }
+
+            let mut imported = vec![];
+            let capabilities = Capabilities::detect();
+            for s in servers {
+                if let Some(f) = find_server_name(&s.name, server_names.as_slice()) {
+                    if server_name_to_source.get(&f) != Some(&source) && !force {
+                        println!("❗ This server already exists: {}", f);
+                        println!("❗  use --force to reconfigure");
+                        continue;
+                    }
+                }
+                mkcert(nginx_dir_path, &s.name, args.verbose > 0);
+                let new_path = found_dir.join(format!("{}.conf", s.name));
+                config::write_server_config(
+                    &new_path,
+                    &s.name,
+                    &s.proxies,
+                    &s.websockets,
+                    &ServerOptions {
+                        logs: Some(log_dir(nginx_dir_path)),
+                        ..Default::default()
+                    },
+                    Some(&source),
+                    &capabilities,
+                );
+                if args.verbose > 0 {
+                    println!(">> Wrote new configuration for server: {}", s.name);
+                }
+                let mut proxies = s.proxies;
+                for ws in s.websockets.iter() {
+                    proxies.insert(ws.location.to_owned(), ws.listing());
+                }
+                server_name_to_proxies.insert(s.name.to_owned(), proxies);
+                imported.push(s.name);
+            }
+
+            // reconcile: remove the servers imported before that are no longer in the file
+            for (name, s) in server_name_to_source.iter() {
+                if *s == source && !imported.contains(name) {
+                    println!("Removing current configuration for: {}", name);
+                    std::fs::remove_file(&server_name_to_path[name]).unwrap();
+                }
+            }
+
+            reload_nginx(args.verbose > 0);
+            for name in imported {
+                print_server(&name, &server_name_to_proxies);
+            }
+            return;
+        }
+        Some(cli::Commands::Stream { command }) => {
+            let stream_dir = match stream_dir {
+                Some(dir) => dir,
+                None => {
+                    println!("❗ No stream directory found in {}", nginx_path);
+                    println!("❗  add one like: stream {{ include streams/*; }}");
+                    return;
+                }
+            };
+            match command {
+                cli::StreamCommand::List {} => {
+                    print_streams(&streams);
+                    if streams.is_empty() {
+                        println!("No stream found.");
+                    }
+                }
+                cli::StreamCommand::Add {
+                    server_name,
+                    port,
+                    target,
+                    mode,
+                    force,
+                } => {
+                    let capabilities = Capabilities::detect();
+                    let stream = stream::Stream {
+                        name: localdev_name(&server_name),
+                        port,
+                        mode,
+                        target: proxy_target(&target, false),
+                    };
+                    let missing: Vec<&str> =
+                        stream::required_modules(std::slice::from_ref(&stream))
+                            .into_iter()
+                            .filter(|m| !capabilities.has(m))
+                            .collect();
+                    if !missing.is_empty() {
+                        println!("❗ nginx is missing the modules: {}", missing.join(", "));
+                        println!("❗  use localdev doctor to check the nginx modules");
+                        return;
+                    }
+                    let same = |s: &stream::Stream| s.name == stream.name && s.port == port;
+                    if streams.iter().any(same) && !force {
+                        println!("❗ This stream already exists: {}:{}", stream.name, port);
+                        println!("❗  use --force to reconfigure");
+                        return;
+                    }
+                    streams.retain(|s| !same(s));
+                    if let Some(other) = streams.iter().find(|s| s.conflicts(&stream)) {
+                        println!(
+                            "❗ The port {} is already used by {} in {} mode",
+                            port, other.name, other.mode
+                        );
+                        return;
+                    }
+                    if stream.mode == stream::StreamMode::Tls {
+                        mkcert(nginx_dir_path, &stream.name, args.verbose > 0);
+                    }
+                    streams.push(stream);
+                    write_streams(&stream_dir, nginx_dir_path, &streams, port);
+                    reload_nginx(args.verbose > 0);
+                    print_streams(&streams);
+                }
+                cli::StreamCommand::Remove { server_name, port } => {
+                    let name = localdev_name(&server_name);
+                    let removed: Vec<u16> = streams
+                        .iter()
+                        .filter(|s| s.name == name && port.is_none_or(|p| p == s.port))
+                        .map(|s| s.port)
+                        .collect();
+                    if removed.is_empty() {
+                        println!("Stream not found: {}", name);
+                        return;
+                    }
+                    println!("Removing the streams of: {}", name);
+                    streams.retain(|s| !(s.name == name && removed.contains(&s.port)));
+                    for p in removed {
+                        write_streams(&stream_dir, nginx_dir_path, &streams, p);
+                    }
+                    reload_nginx(args.verbose > 0);
+                }
+            }
+            return;
+        }
         _ => (),
     }
 
@@ -519,4 +1870,5 @@ fn main() {
     } else {
         println!("No local server found.");
     }
+    print_streams(&streams);
 }
@@ -0,0 +1,272 @@
+use regex::Regex;
+
+/// A feature of the generated configurations and the nginx module it needs
+pub struct Feature {
+    pub name: &'static str,
+    pub module: &'static str,
+}
+
+/// The features that depend on a nginx module, shown by the doctor command
+pub const FEATURES: [Feature; 13] = [
+    Feature {
+        name: "HTTPS",
+        module: "http_ssl_module",
+    },
+    Feature {
+        name: "HTTP/2",
+        module: "http_v2_module",
+    },
+    Feature {
+        name: "HTTP/3",
+        module: "http_v3_module",
+    },
+    Feature {
+        name: "FastCGI targets",
+        module: "http_fastcgi_module",
+    },
+    Feature {
+        name: "uwsgi targets",
+        module: "http_uwsgi_module",
+    },
+    Feature {
+        name: "gRPC targets",
+        module: "http_grpc_module",
+    },
+    Feature {
+        name: "TCP and UDP streams",
+        module: "stream",
+    },
+    Feature {
+        name: "TLS streams",
+        module: "stream_ssl_module",
+    },
+    Feature {
+        name: "Streams routed by server name",
+        module: "stream_ssl_preread_module",
+    },
+    Feature {
+        name: "CORS and error pages",
+        module: "http_rewrite_module",
+    },
+    Feature {
+        name: "Basic auth",
+        module: "http_auth_basic_module",
+    },
+    Feature {
+        name: "Request limits",
+        module: "http_limit_req_module",
+    },
+    Feature {
+        name: "Connection limits",
+        module: "http_limit_conn_module",
+    },
+];
+
+/// The modules built by default, they are only missing when nginx was configured --without them
+const DEFAULT_MODULES: [&str; 8] = [
+    "http_fastcgi_module",
+    "http_grpc_module",
+    "http_uwsgi_module",
+    "http_rewrite_module",
+    "http_proxy_module",
+    "http_limit_req_module",
+    "http_limit_conn_module",
+    "http_auth_basic_module",
+];
+
+/// The version and modules of the installed nginx
+#[derive(Debug, Default)]
+pub struct Capabilities {
+    /// The version, like (1, 25, 3)
+    pub version: Option<(u32, u32, u32)>,
+    /// The configure arguments, None when nginx -V could not be run
+    configure_args: Option<Vec<String>>,
+}
+
+impl Capabilities {
+    /// Run nginx -V and read its output, the capabilities are unknown if it fails
+    pub fn detect() -> Capabilities {
+        match std::process::Command::new("nginx").arg("-V").output() {
+            // the information is printed on stderr
+            Ok(output) => Capabilities::parse(&String::from_utf8_lossy(&output.stderr)),
+            Err(_) => Capabilities::default(),
+        }
+    }
+
+    /// Parse the output of nginx -V, eg: nginx version: nginx/1.25.3 then the configure arguments
+    fn parse(output: &str) -> Capabilities {
+        let version = output
+            .split_once("nginx/")
+            .and_then(|(_, v)| v.split_whitespace().next())
+            .and_then(|v| {
+                let mut parts = v.split('.').map(|p| p.parse::<u32>().ok());
+                Some((
+                    parts.next()??,
+                    parts.next()??,
+                    parts.next().flatten().unwrap_or(0),
+                ))
+            });
+        let configure_args = output
+            .lines()
+            .find_map(|l| l.strip_prefix("configure arguments:"))
+            .map(|args| args.split_whitespace().map(|a| a.to_owned()).collect());
+        Capabilities {
+            version,
+            configure_args,
+        }
+    }
+
+    /// Check if nginx has a module, like http_v2_module or stream, they are all assumed
+    /// to be there when the modules are unknown
+    pub fn has(&self, module: &str) -> bool {
+        let args = match &self.configure_args {
+            Some(args) => args,
+            None => return true,
+        };
+        if DEFAULT_MODULES.contains(&module) {
+            return !args.iter().any(|a| *a == format!("--without-{}", module));
+        }
+        // dynamic modules are given like --with-stream=dynamic
+        let with = format!("--with-{}", module);
+        args.iter()
+            .any(|a| *a == with || a.starts_with(&format!("{}=", with)))
+    }
+
+    /// Check if the modules are known
+    pub fn is_known(&self) -> bool {
+        self.configure_args.is_some()
+    }
+
+    /// Check if HTTP/2 is enabled with its own directive, the listen parameter is deprecated since nginx 1.25.1
+    pub fn http2_directive(&self) -> bool {
+        self.version.is_some_and(|v| v >= (1, 25, 1))
+    }
+}
+
+/// A failure reported by nginx, with the config line at fault and how to fix it
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    /// The config file and line, when nginx gives them
+    pub file: Option<(String, usize)>,
+    pub fix: Option<String>,
+}
+
+/// Read the errors printed by nginx on stderr, eg:
+/// nginx: [emerg] unknown directive "foo" in /etc/nginx/servers/app.localdev.conf:12
+pub fn diagnose(stderr: &str) -> Vec<Diagnostic> {
+    let error_rx = Regex::new(r"^nginx: \[(emerg|alert|crit|error)\] (.*)$").unwrap();
+    let file_rx = Regex::new(r"^(.*) in (\S+):(\d+)$").unwrap();
+    let quoted_rx = Regex::new(r#""([^"]*)""#).unwrap();
+    stderr
+        .lines()
+        .filter_map(|l| error_rx.captures(l.trim()))
+        .map(|c| {
+            let (message, file) = match file_rx.captures(&c[2]) {
+                Some(f) => (
+                    f[1].to_owned(),
+                    Some((f[2].to_owned(), f[3].parse().unwrap())),
+                ),
+                None => (c[2].to_owned(), None),
+            };
+            let quoted = quoted_rx.captures(&message).map(|q| q[1].to_owned());
+            let fix = if message.contains(".pid") && message.contains("No such file")
+                || message.starts_with("invalid PID number")
+            {
+                Some(String::from(
+                    "nginx is not running, start it with: sudo nginx",
+                ))
+            } else if message.contains("Permission denied")
+                || message.contains("Operation not permitted")
+            {
+                Some(String::from(
+                    "nginx runs as another user, run the command again with sudo",
+                ))
+            } else if message.starts_with("unknown directive") {
+                Some(String::from(
+                    "nginx may lack the module of this directive, check it with: localdev doctor",
+                ))
+            } else if message.starts_with("host not found") {
+                quoted.map(|host| {
+                    format!(
+                        "the target {} is not known, check its name or use its IP address",
+                        host
+                    )
+                })
+            } else if message.starts_with("cannot load certificate") {
+                quoted.map(|cert| {
+                    format!(
+                        "the certificate {} is missing, create it again with mkcert",
+                        cert
+                    )
+                })
+            } else {
+                None
+            };
+            Diagnostic { message, file, fix }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_version_and_modules() {
+        let nginx = Capabilities::parse(
+            "nginx version: nginx/1.25.3\n\
+             built with OpenSSL 3.0.2\n\
+             configure arguments: --with-http_ssl_module --with-stream=dynamic --without-http_uwsgi_module\n",
+        );
+        assert_eq!(nginx.version, Some((1, 25, 3)));
+        assert!(nginx.is_known());
+        assert!(nginx.http2_directive());
+        assert!(nginx.has("http_ssl_module"));
+        assert!(nginx.has("stream"));
+        assert!(!nginx.has("http_v2_module"));
+        assert!(nginx.has("http_proxy_module"));
+        assert!(!nginx.has("http_uwsgi_module"));
+    }
+
+    #[test]
+    fn old_or_unknown_nginx() {
+        let nginx = Capabilities::parse("nginx version: nginx/1.18\n");
+        assert_eq!(nginx.version, Some((1, 18, 0)));
+        assert!(!nginx.http2_directive());
+        // without configure arguments every module is assumed
+        assert!(!nginx.is_known());
+        assert!(nginx.has("http_v2_module"));
+        assert_eq!(Capabilities::default().version, None);
+    }
+
+    #[test]
+    fn diagnose_errors() {
+        let diagnostics = diagnose(
+            "nginx: [emerg] unknown directive \"grpc_pass\" in /etc/nginx/servers/app.localdev.conf:12\n\
+             nginx: [warn] the \"http2\" directive is deprecated\n\
+             nginx: [error] open() \"/run/nginx.pid\" failed (2: No such file or directory)\n\
+             nginx: [emerg] host not found in upstream \"api.internal\" in /etc/nginx/servers/app.localdev.conf:30\n\
+             nginx: [emerg] something else\n",
+        );
+        assert_eq!(diagnostics.len(), 4);
+        assert_eq!(diagnostics[0].message, "unknown directive \"grpc_pass\"");
+        assert_eq!(
+            diagnostics[0].file,
+            Some((String::from("/etc/nginx/servers/app.localdev.conf"), 12))
+        );
+        assert!(diagnostics[0]
+            .fix
+            .as_ref()
+            .unwrap()
+            .contains("localdev doctor"));
+        assert_eq!(diagnostics[1].file, None);
+        assert!(diagnostics[1].fix.as_ref().unwrap().contains("not running"));
+        assert!(diagnostics[2]
+            .fix
+            .as_ref()
+            .unwrap()
+            .contains("api.internal"));
+        assert!(diagnostics[3].fix.is_none());
+    }
+}
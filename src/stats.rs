@@ -0,0 +1,293 @@
+use crate::config::{Location, Match};
+use crate::logs::Request;
+use colored::*;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// The traffic of a server read from its access log
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub server: String,
+    pub requests: usize,
+    /// The number of requests by status class, like 2xx
+    pub status: BTreeMap<String, usize>,
+    /// The paths taking the most time to answer on average
+    pub slowest: Vec<PathStats>,
+    pub locations: Vec<LocationStats>,
+}
+
+/// The answer times of the requests of a path
+#[derive(Serialize, Debug)]
+pub struct PathStats {
+    pub path: String,
+    pub requests: usize,
+    pub average_ms: f64,
+    pub max_ms: f64,
+    /// The average time taken by the target, None when it was not proxied
+    pub upstream_average_ms: Option<f64>,
+}
+
+/// The requests of a location and how many failed
+#[derive(Serialize, Debug)]
+pub struct LocationStats {
+    pub location: String,
+    pub target: String,
+    pub requests: usize,
+    /// The requests answered with a 5xx status
+    pub errors: usize,
+    pub error_rate: f64,
+}
+
+/// Find the location serving a path like nginx: the exact locations first, then the regex ones
+/// in their order, else the longest prefix
+fn find_location<'a>(
+    path: &str,
+    locations: &[(&'a str, &Location, Option<Regex>)],
+) -> Option<&'a str> {
+    let exact = locations
+        .iter()
+        .find(|(l, location, _)| location.matching == Match::Exact && *l == path);
+    let regex = || {
+        locations
+            .iter()
+            .find(|(_, _, rx)| rx.as_ref().is_some_and(|rx| rx.is_match(path)))
+    };
+    let prefix = || {
+        locations
+            .iter()
+            .filter(|(l, location, _)| {
+                let l = l.trim_end_matches('/');
+                location.matching == Match::Prefix
+                    && (path == l || path.starts_with(&format!("{}/", l)))
+            })
+            .max_by_key(|(l, _, _)| l.trim_end_matches('/').len())
+    };
+    exact.or_else(regex).or_else(prefix).map(|(l, _, _)| *l)
+}
+
+/// Sum up the requests of a server, with the given number of slowest paths
+pub fn compute(
+    server: &str,
+    requests: &[Request],
+    locations: &HashMap<String, Location>,
+    slowest: usize,
+) -> Stats {
+    let mut sorted: Vec<(&str, &Location, Option<Regex>)> = locations
+        .iter()
+        .map(|(path, l)| {
+            let rx = match l.matching {
+                Match::Regex => Regex::new(path).ok(),
+                _ => None,
+            };
+            (path.as_str(), l, rx)
+        })
+        .collect();
+    // nginx checks the regex locations in the order of the config file
+    sorted.sort_by(|a, b| (a.1.position, a.0).cmp(&(b.1.position, b.0)));
+
+    let mut status = BTreeMap::new();
+    let mut paths: HashMap<&str, Vec<&Request>> = HashMap::new();
+    let mut by_location: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for r in requests {
+        *status.entry(format!("{}xx", r.status / 100)).or_insert(0) += 1;
+        let path = r.uri.split('?').next().unwrap();
+        paths.entry(path).or_default().push(r);
+        if let Some(location) = find_location(path, &sorted) {
+            let (count, errors) = by_location.entry(location).or_default();
+            *count += 1;
+            if r.status >= 500 {
+                *errors += 1;
+            }
+        }
+    }
+
+    let mut slowest_paths: Vec<PathStats> = paths
+        .into_iter()
+        .map(|(path, requests)| {
+            let times: Vec<f64> = requests.iter().map(|r| r.request_time * 1000.0).collect();
+            let upstream: Vec<f64> = requests
+                .iter()
+                .filter_map(|r| r.upstream_seconds())
+                .map(|s| s * 1000.0)
+                .collect();
+            PathStats {
+                path: path.to_owned(),
+                requests: requests.len(),
+                average_ms: times.iter().sum::<f64>() / times.len() as f64,
+                max_ms: times.iter().cloned().fold(0.0, f64::max),
+                upstream_average_ms: Some(upstream.len())
+                    .filter(|n| *n > 0)
+                    .map(|n| upstream.iter().sum::<f64>() / n as f64),
+            }
+        })
+        .collect();
+    slowest_paths.sort_by(|a, b| b.average_ms.total_cmp(&a.average_ms));
+    slowest_paths.truncate(slowest);
+
+    Stats {
+        server: server.to_owned(),
+        requests: requests.len(),
+        status,
+        slowest: slowest_paths,
+        locations: by_location
+            .into_iter()
+            .map(|(location, (requests, errors))| LocationStats {
+                // regex locations are shown after a ~ like in the list
+                location: match locations[location].matching {
+                    Match::Regex => format!("~{}", location),
+                    _ => location.to_owned(),
+                },
+                target: locations[location].target.to_string(),
+                requests,
+                errors,
+                error_rate: errors as f64 / requests as f64,
+            })
+            .collect(),
+    }
+}
+
+/// Print the stats as tables
+pub fn print(stats: &Stats) {
+    println!();
+    println!(
+        " 📊 {}  {} requests",
+        format!("https://{}", stats.server).bold(),
+        stats.requests
+    );
+    if stats.requests == 0 {
+        return;
+    }
+
+    println!();
+    let status: Vec<String> = stats
+        .status
+        .iter()
+        .map(|(class, count)| {
+            let class = match class.as_str() {
+                "2xx" => class.green(),
+                "3xx" => class.cyan(),
+                "4xx" => class.yellow(),
+                _ => class.red(),
+            };
+            format!("{} {}", class, count)
+        })
+        .collect();
+    println!("     {}", status.join("   "));
+
+    // align the paths according to the longest one
+    let l = stats
+        .slowest
+        .iter()
+        .map(|p| p.path.len())
+        .chain(stats.locations.iter().map(|l| l.location.len()))
+        .max()
+        .unwrap_or(0)
+        .max(13);
+    println!();
+    println!(
+        "     {:<l$} {:>8} {:>9} {:>9} {:>9}",
+        "Slowest paths".bold(),
+        "requests",
+        "average",
+        "max",
+        "upstream",
+        l = l
+    );
+    for p in stats.slowest.iter() {
+        let upstream = p
+            .upstream_average_ms
+            .map(|ms| format!("{:.0}ms", ms))
+            .unwrap_or_else(|| String::from("-"));
+        println!(
+            "     {:<l$} {:>8} {:>9} {:>9} {:>9}",
+            p.path,
+            p.requests,
+            format!("{:.0}ms", p.average_ms),
+            format!("{:.0}ms", p.max_ms),
+            upstream,
+            l = l
+        );
+    }
+
+    println!();
+    println!(
+        "     {:<l$} {:>8} {:>9} {:>9}  target",
+        "Locations".bold(),
+        "requests",
+        "5xx",
+        "errors",
+        l = l
+    );
+    for location in stats.locations.iter() {
+        let rate = format!("{:.1}%", location.error_rate * 100.0);
+        let rate = if location.errors > 0 {
+            rate.red()
+        } else {
+            rate.normal()
+        };
+        println!(
+            "     {:<l$} {:>8} {:>9} {:>9}  {}",
+            location.location,
+            location.requests,
+            location.errors,
+            rate,
+            location.target.blue(),
+            l = l
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Target;
+
+    #[test]
+    fn find_locations_like_nginx() {
+        let location = |matching: Match, position: usize| Location {
+            matching,
+            position,
+            ..Location::new(Target::Proxy(String::from("http://localhost:3000/")))
+        };
+        let mut locations = HashMap::new();
+        locations.insert(String::from("/"), location(Match::Prefix, 0));
+        locations.insert(String::from("/api"), location(Match::Prefix, 1));
+        locations.insert(String::from("/api/health"), location(Match::Exact, 2));
+        locations.insert(String::from("\\.png$"), location(Match::Regex, 4));
+        locations.insert(
+            String::from("^/api/.*\\.(png|jpg)$"),
+            location(Match::Regex, 3),
+        );
+        let requests: Vec<Request> = [
+            "/api/health",
+            "/api/logo.png",
+            "/api/users",
+            "/apis",
+            "/logo.png",
+        ]
+        .iter()
+        .filter_map(|uri| {
+            Request::parse(&format!(
+                r#"{{"time": "", "method": "GET", "uri": "{}", "status": 200, "request_time": 0.001}}"#,
+                uri
+            ))
+        })
+        .collect();
+        assert_eq!(requests.len(), 5);
+        let stats = compute("app.localdev", &requests, &locations, 5);
+        let count = |l: &str| {
+            stats
+                .locations
+                .iter()
+                .find(|s| s.location.trim_start_matches('~') == l)
+                .map_or(0, |s| s.requests)
+        };
+        // the first regex in the config file wins over a later one
+        assert_eq!(count("^/api/.*\\.(png|jpg)$"), 1);
+        assert_eq!(count("\\.png$"), 1);
+        assert_eq!(count("/api/health"), 1);
+        assert_eq!(count("/api"), 1);
+        assert_eq!(count("/"), 1);
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    fmt,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Comment before each stream of a port config, followed by its name, port, mode and target
+const STREAM_MARKER: &str = "# localdev-stream:";
+
+/// How a stream is proxied
+#[derive(Debug, Clone, Copy, PartialEq, clap::ArgEnum)]
+pub enum StreamMode {
+    /// Plain TCP, alone on its port
+    Tcp,
+    /// Plain UDP, alone on its port
+    Udp,
+    /// TLS terminated with the mkcert certificate of the server name, routed by SNI
+    Tls,
+    /// TLS passed through to the target, routed by SNI
+    Sni,
+}
+
+impl StreamMode {
+    fn parse(mode: &str) -> Option<StreamMode> {
+        match mode {
+            "tcp" => Some(StreamMode::Tcp),
+            "udp" => Some(StreamMode::Udp),
+            "tls" => Some(StreamMode::Tls),
+            "sni" => Some(StreamMode::Sni),
+            _ => None,
+        }
+    }
+
+    /// The modes sharing a port through the SNI routing
+    pub fn is_routed(&self) -> bool {
+        matches!(self, StreamMode::Tls | StreamMode::Sni)
+    }
+}
+
+impl fmt::Display for StreamMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamMode::Tcp => write!(f, "tcp"),
+            StreamMode::Udp => write!(f, "udp"),
+            StreamMode::Tls => write!(f, "tls"),
+            StreamMode::Sni => write!(f, "sni"),
+        }
+    }
+}
+
+/// A TCP or UDP proxy of a server name on a port
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stream {
+    pub name: String,
+    pub port: u16,
+    pub mode: StreamMode,
+    /// The address proxied to, eg: localhost:15432 or unix:/run/redis.sock
+    pub target: String,
+}
+
+impl Stream {
+    /// The upstream of a stream routed by SNI
+    fn upstream(&self) -> String {
+        format!("localdev-{}-{}", self.name, self.port)
+    }
+
+    /// The unix socket of the server terminating the TLS of a stream
+    fn socket(&self, socket_dir: &Path) -> PathBuf {
+        socket_dir.join(format!("{}-{}.sock", self.name, self.port))
+    }
+
+    /// Check if it can not be on the same port as another stream
+    pub fn conflicts(&self, other: &Stream) -> bool {
+        self.port == other.port
+            && match (self.mode, other.mode) {
+                (StreamMode::Udp, StreamMode::Udp) => true,
+                (StreamMode::Udp, _) | (_, StreamMode::Udp) => false,
+                (a, b) if a.is_routed() && b.is_routed() => self.name == other.name,
+                _ => true,
+            }
+    }
+}
+
+/// The nginx modules needed by the streams
+pub fn required_modules(streams: &[Stream]) -> Vec<&'static str> {
+    let mut modules = vec!["stream"];
+    for stream in streams {
+        let needed: &[&'static str] = match stream.mode {
+            StreamMode::Tls => &["stream_ssl_preread_module", "stream_ssl_module"],
+            StreamMode::Sni => &["stream_ssl_preread_module"],
+            _ => &[],
+        };
+        for module in needed {
+            if !modules.contains(module) {
+                modules.push(module);
+            }
+        }
+    }
+    modules
+}
+
+/// The config file of the streams on a port
+pub fn config_path(dir: &Path, port: u16) -> PathBuf {
+    dir.join(format!("localdev-{}.conf", port))
+}
+
+/// Read the streams of a config file written by localdev
+pub fn parse_config(contents: &str) -> Vec<Stream> {
+    contents
+        .lines()
+        .filter_map(|line| line.trim_start().strip_prefix(STREAM_MARKER))
+        .filter_map(|marker| {
+            let mut parts = marker.split_whitespace();
+            Some(Stream {
+                name: parts.next()?.to_owned(),
+                port: parts.next()?.parse().ok()?,
+                mode: StreamMode::parse(parts.next()?)?,
+                target: parts.next()?.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Write the config file of the streams on a port, the plain TCP or UDP streams listen on it
+/// while the TLS ones are routed by SNI to their upstream, the terminated ones going through
+/// a server listening on a unix socket with the mkcert certificate.
+pub fn write_port_config(path: &Path, port: u16, streams: &[&Stream], socket_dir: &Path) {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .expect("unable to open file");
+    let mut f = BufWriter::new(file);
+
+    for s in streams {
+        writeln!(
+            f,
+            "{} {} {} {} {}",
+            STREAM_MARKER, s.name, s.port, s.mode, s.target
+        )
+        .unwrap();
+    }
+
+    // the plain streams
+    for s in streams.iter().filter(|s| !s.mode.is_routed()) {
+        f.write_all(b"server {\n").unwrap();
+        if s.mode == StreamMode::Udp {
+            writeln!(f, "  listen {} udp;", port).unwrap();
+        } else {
+            writeln!(f, "  listen {};", port).unwrap();
+        }
+        writeln!(f, "  proxy_pass {};", s.target).unwrap();
+        f.write_all(b"}\n").unwrap();
+    }
+
+    // the streams routed by SNI
+    let routed: Vec<&&Stream> = streams.iter().filter(|s| s.mode.is_routed()).collect();
+    if routed.is_empty() {
+        f.flush().unwrap();
+        return;
+    }
+    writeln!(
+        f,
+        "map $ssl_preread_server_name $localdev_stream_{} {{",
+        port
+    )
+    .unwrap();
+    for s in routed.iter() {
+        writeln!(f, "  {} {};", s.name, s.upstream()).unwrap();
+    }
+    f.write_all(b"}\n").unwrap();
+    f.write_all(b"server {\n").unwrap();
+    writeln!(f, "  listen {};", port).unwrap();
+    f.write_all(b"  ssl_preread on;\n").unwrap();
+    writeln!(f, "  proxy_pass $localdev_stream_{};", port).unwrap();
+    f.write_all(b"}\n").unwrap();
+
+    for s in routed {
+        // an upstream does not need a resolver when chosen from a variable
+        writeln!(f, "upstream {} {{", s.upstream()).unwrap();
+        match s.mode {
+            StreamMode::Tls => {
+                writeln!(f, "  server unix:{};", s.socket(socket_dir).display()).unwrap()
+            }
+            _ => writeln!(f, "  server {};", s.target).unwrap(),
+        }
+        f.write_all(b"}\n").unwrap();
+        if s.mode == StreamMode::Tls {
+            f.write_all(b"server {\n").unwrap();
+            writeln!(f, "  listen unix:{} ssl;", s.socket(socket_dir).display()).unwrap();
+            writeln!(f, "  ssl_certificate      {}.pem;", s.name).unwrap();
+            writeln!(f, "  ssl_certificate_key  {}-key.pem;", s.name).unwrap();
+            writeln!(f, "  proxy_pass {};", s.target).unwrap();
+            f.write_all(b"}\n").unwrap();
+        }
+    }
+
+    // done writing
+    f.flush().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(name: &str, port: u16, mode: StreamMode, target: &str) -> Stream {
+        Stream {
+            name: name.to_owned(),
+            port,
+            mode,
+            target: target.to_owned(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let streams = vec![
+            stream("db.localdev", 5432, StreamMode::Tls, "localhost:15432"),
+            stream(
+                "cache.localdev",
+                5432,
+                StreamMode::Sni,
+                "unix:/run/redis.sock",
+            ),
+        ];
+        let path =
+            std::env::temp_dir().join(format!("localdev-stream-{}.conf", std::process::id()));
+        let on_port: Vec<&Stream> = streams.iter().collect();
+        write_port_config(&path, 5432, &on_port, Path::new("/tmp"));
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(parse_config(&contents), streams);
+    }
+
+    #[test]
+    fn conflicts() {
+        let tcp = stream("db.localdev", 5432, StreamMode::Tcp, "localhost:15432");
+        let udp = stream("dns.localdev", 5432, StreamMode::Udp, "localhost:1053");
+        let tls = stream("a.localdev", 5432, StreamMode::Tls, "localhost:1");
+        let sni = stream("b.localdev", 5432, StreamMode::Sni, "localhost:2");
+        assert!(!tcp.conflicts(&udp));
+        assert!(tcp.conflicts(&tls));
+        assert!(udp.conflicts(&udp));
+        assert!(!tls.conflicts(&sni));
+        assert!(tls.conflicts(&tls));
+        assert!(!tcp.conflicts(&stream("db.localdev", 5433, StreamMode::Tcp, "localhost:1")));
+    }
+}